@@ -1,369 +1,6297 @@
-use anchor_lang::prelude::*;
-
-declare_id!("6WVh9yhUaofmUMAsK1EuCJG5ptzZPzKqj7LcFDVzLgnA");
-
-#[program]
-pub mod nautilink {
-    use super::*;
-
-    /// Creates the initial crate record (no parents)
-    pub fn create_crate(
-        ctx: Context<CreateCrate>,
-        crate_id: String,
-        crate_did: String,
-        owner_did: String,
-        device_did: String,
-        location: String,
-        weight: u32,
-        timestamp: i64,
-        hash: String,
-        ipfs_cid: String,
-    ) -> Result<()> {
-        let record = &mut ctx.accounts.crate_record;
-        record.crate_id = crate_id;
-        record.crate_did = crate_did;
-        record.owner_did = owner_did;
-        record.device_did = device_did;
-        record.location = location;
-        record.weight = weight;
-        record.timestamp = timestamp;
-        record.hash = hash;
-        record.ipfs_cid = ipfs_cid;
-        record.authority = ctx.accounts.authority.key();
-        record.parent_crates = Vec::new();
-        record.child_crates = Vec::new();
-        record.parent_weights = Vec::new();
-        record.split_distribution = Vec::new();
-        record.operation_type = OperationType::Created;
-        Ok(())
-
-    }
-
-    /// Transfers ownership without changing weight
-    pub fn transfer_ownership(
-        ctx: Context<TransferOwnership>,
-        crate_id: String,
-        crate_did: String,
-        owner_did: String,
-        device_did: String,
-        location: String,
-        weight: u32,
-        timestamp: i64,
-
-        hash: String,
-        ipfs_cid: String,
-    ) -> Result<()> {
-        let parent = &ctx.accounts.parent_crate;
-
-        require_keys_eq!(
-            ctx.accounts.authority.key(),
-            parent.authority,
-            ErrorCode::UnauthorizedUpdate
-        );
-
-        require!(weight == parent.weight, ErrorCode::WeightMismatchOnTransfer);
-
-        let record = &mut ctx.accounts.crate_record;
-        record.crate_id = crate_id;
-        record.crate_did = crate_did;
-        record.owner_did = owner_did;
-        record.device_did = device_did;
-        record.location = location;
-        record.weight = weight;
-        record.timestamp = timestamp;
-        record.hash = hash;
-        record.ipfs_cid = ipfs_cid;
-        record.authority = ctx.accounts.authority.key();
-        record.parent_crates = vec![parent.key()];
-        record.child_crates = Vec::new();
-        record.parent_weights = vec![parent.weight];
-        record.split_distribution = Vec::new();
-        record.operation_type = OperationType::Transferred;
-
-        Ok(())
-    }
-
-
-    /// Mixes multiple parent crates into one
-    pub fn mix_crates<'info>(
-        ctx: Context<'_, '_, 'info, 'info, MixCrates<'info>>,
-        crate_id: String,
-        crate_did: String,
-        owner_did: String,
-        device_did: String,
-        location: String,
-
-        timestamp: i64,
-        hash: String,
-        ipfs_cid: String,
-        parent_keys: Vec<Pubkey>,
-    ) -> Result<()> {
-        require!(parent_keys.len() >= 2, ErrorCode::MixRequiresMultipleParents);
-
-        require!(parent_keys.len() <= CrateRecord::MAX_PARENTS, ErrorCode::TooManyParents);
-
-        let mut total_weight: u32 = 0;
-        let mut parent_weights = Vec::new();
-
-        for parent_info in ctx.remaining_accounts.iter() {
-            let parent: Account<CrateRecord> = Account::try_from(parent_info)?;
-            require_keys_eq!(
-                parent.authority,
-                ctx.accounts.authority.key(),
-                ErrorCode::UnauthorizedUpdate
-            );
-
-
-            total_weight = total_weight
-                .checked_add(parent.weight)
-                .ok_or(ErrorCode::WeightOverflow)?;
-            parent_weights.push(parent.weight);
-        }
-
-        let record = &mut ctx.accounts.crate_record;
-        record.crate_id = crate_id;
-        record.crate_did = crate_did;
-        record.owner_did = owner_did;
-        record.device_did = device_did;
-        record.location = location;
-        record.weight = total_weight;
-        record.timestamp = timestamp;
-        record.hash = hash;
-
-        record.ipfs_cid = ipfs_cid;
-
-        record.authority = ctx.accounts.authority.key();
-        record.parent_crates = parent_keys;
-        record.child_crates = Vec::new();
-        record.parent_weights = parent_weights;
-        record.split_distribution = Vec::new();
-        record.operation_type = OperationType::Mixed;
-
-        Ok(())
-    }
-
-    /// Splits one crate into multiple child crates
-    pub fn split_crate(
-        ctx: Context<SplitCrate>,
-        crate_id: String,
-        crate_did: String,
-        owner_did: String,
-        device_did: String,
-        location: String,
-        weight: u32,
-        timestamp: i64,
-        hash: String,
-        ipfs_cid: String,
-        child_keys: Vec<Pubkey>,
-        child_weights: Vec<u32>,
-    ) -> Result<()> {
-        let parent = &ctx.accounts.parent_crate;
-
-        require_keys_eq!(
-            ctx.accounts.authority.key(),
-            parent.authority,
-            ErrorCode::UnauthorizedUpdate
-        );
-
-        require!(child_keys.len() >= 2, ErrorCode::SplitRequiresMultipleChildren);
-        require!(child_keys.len() <= CrateRecord::MAX_CHILDREN, ErrorCode::TooManyChildren);
-
-        require!(
-            child_keys.len() == child_weights.len(),
-            ErrorCode::ChildKeyWeightMismatch
-        );
-
-        let total_child_weight: u32 = child_weights.iter().sum();
-
-        require!(total_child_weight == parent.weight, ErrorCode::SplitWeightMismatch);
-
-
-        let record = &mut ctx.accounts.crate_record;
-        record.crate_id = crate_id;
-        record.crate_did = crate_did;
-        record.owner_did = owner_did;
-        record.device_did = device_did;
-        record.location = location;
-        record.weight = weight;
-        record.timestamp = timestamp;
-        record.hash = hash;
-        record.ipfs_cid = ipfs_cid;
-        record.authority = ctx.accounts.authority.key();
-        record.parent_crates = vec![parent.key()];
-        record.child_crates = child_keys.clone();
-        record.parent_weights = vec![parent.weight];
-        record.split_distribution = child_weights;
-        record.operation_type = OperationType::Split;
-
-        Ok(())
-    }
-
-    pub fn update_parent_children(
-        ctx: Context<UpdateParent>,
-        child_keys: Vec<Pubkey>,
-    ) -> Result<()> {
-        let parent = &mut ctx.accounts.parent_crate;
-        require_keys_eq!(ctx.accounts.authority.key(), parent.authority, ErrorCode::UnauthorizedUpdate);
-        parent.child_crates = child_keys;
-        Ok(())
-    }
-
-    pub fn update_child_parent(
-        ctx: Context<UpdateChild>,
-        parent_key: Pubkey,
-    ) -> Result<()> {
-        let child = &mut ctx.accounts.child_crate;
-        require_keys_eq!(ctx.accounts.authority.key(), child.authority, ErrorCode::UnauthorizedUpdate);
-        if !child.parent_crates.contains(&parent_key) {
-            child.parent_crates.push(parent_key);
-        }
-        Ok(())
-    }
-}
-
-// ===================
-// CONTEXTS
-// ===================
-
-#[derive(Accounts)]
-#[instruction(crate_id: String)]
-pub struct CreateCrate<'info> {
-    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
-    pub crate_record: Account<'info, CrateRecord>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-#[instruction(crate_id: String)]
-pub struct TransferOwnership<'info> {
-    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
-    pub crate_record: Account<'info, CrateRecord>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    pub parent_crate: Account<'info, CrateRecord>,
-    pub system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-#[instruction(crate_id: String)]
-pub struct MixCrates<'info> {
-    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
-    pub crate_record: Account<'info, CrateRecord>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-#[instruction(crate_id: String)]
-pub struct SplitCrate<'info> {
-    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
-    pub crate_record: Account<'info, CrateRecord>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    pub parent_crate: Account<'info, CrateRecord>,
-    pub system_program: Program<'info, System>,
-}
-
-
-#[derive(Accounts)]
-pub struct UpdateParent<'info> {
-    #[account(mut)]
-    pub parent_crate: Account<'info, CrateRecord>,
-    pub authority: Signer<'info>,
-}
-
-#[derive(Accounts)]
-pub struct UpdateChild<'info> {
-    #[account(mut)]
-    pub child_crate: Account<'info, CrateRecord>,
-    pub authority: Signer<'info>,
-}
-
-// ===================
-// DATA STRUCTURE
-// ===================
-
-#[account]
-
-pub struct CrateRecord {
-    pub authority: Pubkey,
-    pub crate_id: String,
-    pub crate_did: String,  // DID for crate
-    pub owner_did: String,  // DID for owner
-    pub device_did: String, // DID for NFC or scanner device
-    pub location: String,   // Location string (lat,long)
-    pub weight: u32,
-    pub timestamp: i64,
-    pub hash: String,
-    pub ipfs_cid: String,
-
-    pub parent_crates: Vec<Pubkey>,
-    pub child_crates: Vec<Pubkey>,
-    pub parent_weights: Vec<u32>,
-    pub split_distribution: Vec<u32>,
-    pub operation_type: OperationType,
-
-}
-
-impl CrateRecord {
-
-    pub const MAX_PARENTS: usize = 10;
-    pub const MAX_CHILDREN: usize = 10;
-    pub const MAX_SIZE: usize =
-        32 + // authority
-        4 + 64 + // crate_id
-        4 + 64 + // crate_did
-        4 + 64 + // owner_did
-        4 + 64 + // device_did
-        4 + 64 + // location
-        4 + 8 +  // weight + timestamp
-        4 + 64 + // hash
-        4 + 64 + // ipfs_cid
-        4 + (Self::MAX_PARENTS * 32) +
-        4 + (Self::MAX_CHILDREN * 32) +
-        4 + (Self::MAX_PARENTS * 4) +
-        4 + (Self::MAX_CHILDREN * 4) +
-        1;
-}
-
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
-pub enum OperationType {
-    Created,
-    Transferred,
-    Mixed,
-    Split,
-}
-
-// ===================
-// ERRORS
-// ===================
-
-
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Weight must remain the same during transfer")]
-    WeightMismatchOnTransfer,
-    #[msg("Mix requires at least 2 parents")]
-
-    MixRequiresMultipleParents,
-    #[msg("Split requires at least 2 children")]
-    SplitRequiresMultipleChildren,
-
-    #[msg("Too many parents (max 10)")]
-    TooManyParents,
-    #[msg("Too many children (max 10)")]
-    TooManyChildren,
-
-    #[msg("Child key/weight mismatch")]
-    ChildKeyWeightMismatch,
-    #[msg("Split weights do not sum to parent weight")]
-    SplitWeightMismatch,
-
-    #[msg("Weight overflow")]
-    WeightOverflow,
-    #[msg("Unauthorized update attempt")]
-    UnauthorizedUpdate,
-}
+#![allow(clippy::too_many_arguments)]
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{
+    allocate, assign, create_account, transfer, Allocate, Assign, CreateAccount, Transfer,
+};
+use solana_instructions_sysvar::{load_current_index_checked, load_instruction_at_checked};
+use solana_sdk_ids::{ed25519_program, sysvar};
+use solana_sha256_hasher::hashv;
+
+declare_id!("6WVh9yhUaofmUMAsK1EuCJG5ptzZPzKqj7LcFDVzLgnA");
+
+#[program]
+pub mod nautilink {
+    use super::*;
+
+    /// Creates the initial crate record (no parents). It cannot be transferred until
+    /// `min_hold_seconds` have passed since `timestamp` (quarantine, aging).
+    pub fn create_crate(
+        ctx: Context<CreateCrate>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        weight: u32,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        weight_unit: WeightUnit,
+        min_hold_seconds: u32,
+        display_decimals: u8,
+    ) -> Result<()> {
+        require!(
+            display_decimals <= CrateRecord::MAX_DISPLAY_DECIMALS,
+            ErrorCode::DisplayDecimalsTooLarge
+        );
+        init_crate(
+            ctx.accounts, crate_id, crate_did, owner_did, device_did, location, weight,
+            timestamp, hash, ipfs_cid, weight_unit,
+        )?;
+        ctx.accounts.config.require_fields(&ctx.accounts.crate_record)?;
+        ctx.accounts.crate_record.min_hold_seconds = min_hold_seconds;
+        ctx.accounts.crate_record.display_decimals = display_decimals;
+        Ok(())
+    }
+
+    /// Claims `crate_id` for the signer until the reservation TTL passes; `create_crate`
+    /// consumes it. An expired reservation may be claimed by anyone.
+    pub fn reserve_crate_id(ctx: Context<ReserveCrateId>, _crate_id: String) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let reservation = &mut ctx.accounts.reservation;
+        require!(
+            reservation.reserver == Pubkey::default() || now >= reservation.expires_at,
+            ErrorCode::CrateIdAlreadyReserved
+        );
+        reservation.reserver = ctx.accounts.reserver.key();
+        reservation.expires_at = now + ctx.accounts.config.reservation_ttl_secs();
+        Ok(())
+    }
+
+    /// Registers a supplier that crates can reference instead of repeating its details (admin only)
+    pub fn register_supplier(
+        ctx: Context<RegisterSupplier>,
+        supplier_id: String,
+        name: String,
+        certifications: Vec<String>,
+        contact_cid: String,
+    ) -> Result<()> {
+        require!(name.len() <= Supplier::MAX_NAME_LEN, ErrorCode::SupplierFieldTooLong);
+        require!(contact_cid.len() <= CrateRecord::MAX_CID_LEN, ErrorCode::SupplierFieldTooLong);
+        require!(
+            certifications.len() <= Supplier::MAX_CERTIFICATIONS,
+            ErrorCode::TooManyCertifications
+        );
+        require!(
+            certifications.iter().all(|standard| standard.len() <= Certification::MAX_STANDARD_LEN),
+            ErrorCode::StandardTooLong
+        );
+        let supplier = &mut ctx.accounts.supplier;
+        supplier.supplier_id = supplier_id;
+        supplier.name = name;
+        supplier.certifications = certifications;
+        supplier.contact_cid = contact_cid;
+        Ok(())
+    }
+
+    /// Stores reusable defaults for crates of one product type
+    pub fn create_template(
+        ctx: Context<CreateTemplate>,
+        template_id: String,
+        product_type: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        weight_unit: WeightUnit,
+    ) -> Result<()> {
+        require!(
+            product_type.len() <= CrateRecord::MAX_PRODUCT_TYPE_LEN,
+            ErrorCode::ProductTypeTooLong
+        );
+        let template = &mut ctx.accounts.template;
+        template.authority = ctx.accounts.authority.key();
+        template.template_id = template_id;
+        template.product_type = product_type;
+        template.owner_did = owner_did;
+        template.device_did = device_did;
+        template.location = location;
+        template.weight_unit = weight_unit;
+        Ok(())
+    }
+
+    /// Attaches a certification to a template, copied into every crate created from it.
+    /// The certifier co-signs, as with `add_certification`.
+    pub fn add_template_certification(
+        ctx: Context<AddTemplateCertification>,
+        standard: String,
+        expiry: i64,
+    ) -> Result<()> {
+        let template = &mut ctx.accounts.template;
+        require!(standard.len() <= Certification::MAX_STANDARD_LEN, ErrorCode::StandardTooLong);
+        require!(
+            template.certifications.len() < CrateRecord::MAX_CERTIFICATIONS,
+            ErrorCode::TooManyCertifications
+        );
+        template.certifications.push(Certification {
+            certifier: ctx.accounts.certifier.key(),
+            standard,
+            expiry,
+            revoked: false,
+        });
+        Ok(())
+    }
+
+    /// Creates a crate from `template`; only the per-crate fields are supplied
+    pub fn create_from_template(
+        ctx: Context<CreateFromTemplate>,
+        crate_id: String,
+        crate_did: String,
+        weight: u32,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+    ) -> Result<()> {
+        let template = &ctx.accounts.template;
+        require_keys_eq!(
+            ctx.accounts.create.authority.key(),
+            template.authority,
+            ErrorCode::UnauthorizedUpdate
+        );
+        init_crate(
+            &mut ctx.accounts.create,
+            crate_id,
+            crate_did,
+            template.owner_did.clone(),
+            template.device_did.clone(),
+            template.location.clone(),
+            weight,
+            timestamp,
+            hash,
+            ipfs_cid,
+            template.weight_unit,
+        )?;
+
+        let record = &mut ctx.accounts.create.crate_record;
+        record.product_type = template.product_type.clone();
+        record.certifications = template.certifications.clone();
+        ctx.accounts.create.config.require_fields(record)?;
+        Ok(())
+    }
+
+    /// Transfers ownership within the configured weight tolerance. With `vault_controller`
+    /// set, the new record is owned by that approved program's vault PDA instead.
+    pub fn transfer_ownership(
+        ctx: Context<TransferOwnership>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        weight: u32,
+        timestamp: i64,
+
+        hash: String,
+        ipfs_cid: String,
+        carbon_grams: u64,
+        vault_controller: Option<Pubkey>,
+    ) -> Result<()> {
+        transfer_record(
+            ctx.accounts, crate_id, crate_did, owner_did, device_did, location, weight,
+            timestamp, hash, ipfs_cid, carbon_grams, vault_controller,
+        )?;
+        let accounts = ctx.accounts;
+        accounts
+            .config
+            .apply_operation_status(&mut accounts.crate_record, Clock::get()?.unix_timestamp)
+    }
+
+    /// Transfers ownership and moves the new record to `status` in one call; `status`
+    /// replaces any configured default for transfers
+    pub fn transfer_with_status(
+        ctx: Context<TransferOwnership>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        weight: u32,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        carbon_grams: u64,
+        status: CrateStatus,
+    ) -> Result<()> {
+        transfer_record(
+            ctx.accounts, crate_id, crate_did, owner_did, device_did, location, weight,
+            timestamp, hash, ipfs_cid, carbon_grams, None,
+        )?;
+        ctx.accounts
+            .crate_record
+            .set_status(status, Clock::get()?.unix_timestamp)
+    }
+
+
+    /// Sends a crate back up the chain. The new record's parent is the returned crate
+    /// and its authority is the owner of `upstream_crate`, the returned crate's parent.
+    pub fn record_return(
+        ctx: Context<RecordReturn>,
+        crate_id: String,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        return_reason_cid: String,
+    ) -> Result<()> {
+        let parent = &ctx.accounts.parent_crate;
+        let upstream = &ctx.accounts.upstream_crate;
+        require_keys_eq!(ctx.accounts.returner.key(), parent.authority, ErrorCode::UnauthorizedUpdate);
+        // The returned goods change hands to the upstream owner
+        parent.prepare_transfer(&ctx.accounts.config)?;
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::Returned),
+            ErrorCode::OperationDisabled
+        );
+        require!(parent.parent_crates.contains(&upstream.key()), ErrorCode::NotAnAncestor);
+        require!(return_reason_cid.len() <= CrateRecord::MAX_CID_LEN, ErrorCode::ReturnReasonCidTooLong);
+        require!(
+            !ctx.accounts.config.enforce_timestamp_order || timestamp >= parent.timestamp,
+            ErrorCode::TimestampBeforeParent
+        );
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        let record = &mut ctx.accounts.crate_record;
+        record.crate_id = crate_id;
+        record.crate_did = parent.crate_did.clone();
+        record.owner_did = upstream.owner_did.clone();
+        record.device_did = parent.device_did.clone();
+        record.location = parent.location.clone();
+        record.weight = parent.weight;
+        record.timestamp = timestamp;
+        record.hash = hash;
+        record.ipfs_cid = ipfs_cid;
+        record.authority = upstream.authority;
+        record.parent_crates = vec![parent.key()];
+        record.child_crates = Vec::new();
+        record.parent_weights = vec![parent.weight];
+        record.weight_unit = parent.weight_unit;
+        record.split_distribution = Vec::new();
+        record.operation_type = OperationType::Returned;
+        record.lot_code = parent.lot_code.clone();
+        record.expiry = parent.expiry;
+        record.carbon_grams = parent.carbon_grams;
+        record.return_reason_cid = return_reason_cid;
+        record.transfer_count = parent.transfer_count.saturating_add(1);
+        record.lineage_hash = lineage_hash(&record.hash, &[parent.lineage_hash]);
+        require!(record.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+        ctx.accounts.config.apply_operation_status(record, Clock::get()?.unix_timestamp)?;
+
+        let (child, weight) = (record.key(), record.weight);
+        ctx.accounts.parent_crate.consume_into(child, weight)
+    }
+
+    /// Mixes multiple parent crates into one. `parent_grades`, if not empty, holds one
+    /// grade per parent in `parent_keys` order and is weight-averaged into `blended_grade`.
+    pub fn mix_crates<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MixCrates<'info>>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        parent_keys: Vec<Pubkey>,
+        carbon_grams: u64,
+        parent_grades: Vec<u16>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::Mixed),
+            ErrorCode::OperationDisabled
+        );
+        let mix_key = ctx.accounts.crate_record.key();
+        let parents = prepare_mix(
+            &ctx.accounts.config,
+            ctx.remaining_accounts,
+            &parent_keys,
+            &parent_grades,
+            &[],
+            ctx.accounts.authority.key(),
+            mix_key,
+            timestamp,
+        )?;
+        let total_weight = u32::try_from(parents.total_grams).map_err(|_| ErrorCode::WeightOverflow)?;
+        consume_mix_parents(ctx.remaining_accounts, |parent| {
+            let weight = parent.weight;
+            parent.consume_into(mix_key, weight)
+        })?;
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        let record = &mut ctx.accounts.crate_record;
+        record.crate_id = crate_id;
+        record.crate_did = crate_did;
+        record.owner_did = owner_did;
+        record.device_did = device_did;
+        record.location = location;
+        record.weight = total_weight;
+        record.timestamp = timestamp;
+        record.hash = hash;
+
+        record.ipfs_cid = ipfs_cid;
+
+        record.authority = ctx.accounts.authority.key();
+        record.parent_crates = parent_keys;
+        record.child_crates = Vec::new();
+        record.split_distribution = Vec::new();
+        record.operation_type = OperationType::Mixed;
+        record.carbon_grams = parents.carbon_grams.saturating_add(carbon_grams);
+        record.cached_parent_weight_sum = total_weight;
+        inherit_mix_parents(record, parents)?;
+        ctx.accounts.config.apply_operation_status(record, Clock::get()?.unix_timestamp)
+    }
+
+    /// Mixes the parents in `remaining_accounts` and creates one child of the declared
+    /// split. Each child is created by its own call with the same `child_keys`/`child_weights`.
+    /// Declared output may differ from the input by up to `mix_tolerance_bps`; the difference
+    /// is recorded in `mix_weight_delta`. Parents and `parent_grades` are as for `mix_crates`.
+    pub fn mix_and_split<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MixCrates<'info>>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        parent_keys: Vec<Pubkey>,
+        child_keys: Vec<Pubkey>,
+        child_weights: Vec<u32>,
+        parent_grades: Vec<u16>,
+    ) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(
+            config.is_operation_enabled(OperationType::MixedAndSplit),
+            ErrorCode::OperationDisabled
+        );
+        require!(child_keys.len() >= 2, ErrorCode::SplitRequiresMultipleChildren);
+        require!(child_keys.len() <= CrateRecord::MAX_CHILDREN, ErrorCode::TooManyChildren);
+        require!(child_keys.len() == child_weights.len(), ErrorCode::ChildKeyWeightMismatch);
+
+        let parents = prepare_mix(
+            config,
+            ctx.remaining_accounts,
+            &parent_keys,
+            &parent_grades,
+            &child_keys,
+            ctx.accounts.authority.key(),
+            ctx.accounts.crate_record.key(),
+            timestamp,
+        )?;
+
+        let declared_grams: u64 = child_weights.iter().map(|w| *w as u64).sum();
+        let delta = declared_grams.abs_diff(parents.total_grams);
+        require!(
+            delta * BPS_DENOMINATOR as u64 <= parents.total_grams * config.mix_tolerance_bps as u64,
+            ErrorCode::MixConservationExceeded
+        );
+        let mix_weight_delta = i64::try_from(declared_grams).map_err(|_| ErrorCode::WeightOverflow)?
+            - i64::try_from(parents.total_grams).map_err(|_| ErrorCode::WeightOverflow)?;
+
+        let record_key = ctx.accounts.crate_record.key();
+        let index = child_keys
+            .iter()
+            .position(|key| *key == record_key)
+            .ok_or(ErrorCode::ChildNotInSplit)?;
+        let weight = child_weights[index];
+        consume_mix_parents(ctx.remaining_accounts, |parent| {
+            parent.consume_into_split(&child_keys, &[])
+        })?;
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        let record = &mut ctx.accounts.crate_record;
+        record.crate_id = crate_id;
+        record.crate_did = crate_did;
+        record.owner_did = owner_did;
+        record.device_did = device_did;
+        record.location = location;
+        record.weight = weight;
+        record.timestamp = timestamp;
+        record.hash = hash;
+        record.ipfs_cid = ipfs_cid;
+        record.authority = ctx.accounts.authority.key();
+        record.parent_crates = parent_keys;
+        record.child_crates = child_keys;
+        record.split_distribution = child_weights;
+        record.operation_type = OperationType::MixedAndSplit;
+        record.carbon_grams = if declared_grams == 0 {
+            0
+        } else {
+            (parents.carbon_grams as u128 * weight as u128 / declared_grams as u128) as u64
+        };
+        record.mix_weight_delta = mix_weight_delta;
+        record.cached_parent_weight_sum =
+            u32::try_from(parents.total_grams).map_err(|_| ErrorCode::WeightOverflow)?;
+        inherit_mix_parents(record, parents)?;
+        ctx.accounts.config.apply_operation_status(record, Clock::get()?.unix_timestamp)
+    }
+
+    /// Splits one crate into multiple child crates
+    pub fn split_crate(
+        ctx: Context<SplitCrate>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        weight: u32,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        child_keys: Vec<Pubkey>,
+        child_weights: Vec<u32>,
+        carbon_grams: u64,
+    ) -> Result<()> {
+        let parent = &mut ctx.accounts.parent_crate;
+
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            parent.authority,
+            ErrorCode::UnauthorizedUpdate
+        );
+        parent.prepare_split_derivation(&ctx.accounts.config)?;
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::Split),
+            ErrorCode::OperationDisabled
+        );
+        require!(
+            !ctx.accounts.config.enforce_timestamp_order || timestamp >= parent.timestamp,
+            ErrorCode::TimestampBeforeParent
+        );
+
+        require!(child_keys.len() >= 2, ErrorCode::SplitRequiresMultipleChildren);
+        require!(child_keys.len() <= CrateRecord::MAX_CHILDREN, ErrorCode::TooManyChildren);
+
+        require!(
+            child_keys.len() == child_weights.len(),
+            ErrorCode::ChildKeyWeightMismatch
+        );
+
+        let total_child_weight: u32 = child_weights.iter().sum();
+
+        require!(total_child_weight == parent.weight, ErrorCode::SplitWeightMismatch);
+
+        // Strict mode: the children come in `remaining_accounts` in `child_keys` order and
+        // this child must be created with its own entry of `child_weights`
+        if ctx.accounts.config.strict_split_order {
+            require!(
+                ctx.remaining_accounts.len() == child_keys.len()
+                    && ctx.remaining_accounts.iter().zip(child_keys.iter()).all(|(info, key)| info.key == key),
+                ErrorCode::ChildOrderMismatch
+            );
+            let index = child_keys
+                .iter()
+                .position(|key| *key == ctx.accounts.crate_record.key())
+                .ok_or(ErrorCode::ChildNotInSplit)?;
+            require!(weight == child_weights[index], ErrorCode::ChildOrderMismatch);
+        }
+        if ctx.accounts.config.distinct_child_authorities {
+            require_distinct_child_authorities(
+                ctx.remaining_accounts,
+                &child_keys,
+                ctx.accounts.crate_record.key(),
+                ctx.accounts.authority.key(),
+            )?;
+        }
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        parent.consume_into_split(&child_keys, &child_weights)?;
+        init_split_child(
+            &mut ctx.accounts.crate_record,
+            parent,
+            &ctx.accounts.config,
+            crate_id,
+            crate_did,
+            owner_did,
+            device_did,
+            location,
+            weight,
+            timestamp,
+            hash,
+            ipfs_cid,
+            ctx.accounts.authority.key(),
+            child_keys,
+            child_weights,
+            carbon_grams,
+        )
+    }
+
+    /// Splits one crate where the last child receives whatever weight the others leave.
+    /// `child_keys`/`child_weights` cover every child except `last_child_key`.
+    pub fn split_crate_auto_remainder(
+        ctx: Context<SplitCrateAutoRemainder>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        child_keys: Vec<Pubkey>,
+        child_weights: Vec<u32>,
+        last_child_key: Pubkey,
+    ) -> Result<()> {
+        let parent = &mut ctx.accounts.parent_crate;
+
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            parent.authority,
+            ErrorCode::UnauthorizedUpdate
+        );
+        parent.prepare_split_derivation(&ctx.accounts.config)?;
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::Split),
+            ErrorCode::OperationDisabled
+        );
+        require!(
+            !ctx.accounts.config.enforce_timestamp_order || timestamp >= parent.timestamp,
+            ErrorCode::TimestampBeforeParent
+        );
+
+        require!(!child_keys.is_empty(), ErrorCode::SplitRequiresMultipleChildren);
+        require!(child_keys.len() < CrateRecord::MAX_CHILDREN, ErrorCode::TooManyChildren);
+
+        require!(
+            child_keys.len() == child_weights.len(),
+            ErrorCode::ChildKeyWeightMismatch
+        );
+
+        let mut provided_weight: u32 = 0;
+        for weight in child_weights.iter() {
+            provided_weight = provided_weight
+                .checked_add(*weight)
+                .ok_or(ErrorCode::WeightOverflow)?;
+        }
+        let remainder = parent
+            .weight
+            .checked_sub(provided_weight)
+            .filter(|remainder| *remainder > 0)
+            .ok_or(ErrorCode::InvalidSplitRemainder)?;
+
+        let mut all_keys = child_keys;
+        all_keys.push(last_child_key);
+        let mut all_weights = child_weights;
+        all_weights.push(remainder);
+
+        let child_index = all_keys
+            .iter()
+            .position(|key| *key == ctx.accounts.crate_record.key())
+            .ok_or(ErrorCode::ChildNotInSplit)?;
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        let weight = all_weights[child_index];
+        parent.consume_into_split(&all_keys, &all_weights)?;
+        init_split_child(
+            &mut ctx.accounts.crate_record,
+            parent,
+            &ctx.accounts.config,
+            crate_id,
+            crate_did,
+            owner_did,
+            device_did,
+            location,
+            weight,
+            timestamp,
+            hash,
+            ipfs_cid,
+            ctx.accounts.authority.key(),
+            all_keys,
+            all_weights,
+            0,
+        )
+    }
+
+    /// Splits one crate into children sized by basis points of the parent weight.
+    /// Rounding remainders are assigned according to the configured `RoundingPolicy`.
+    pub fn split_crate_bps(
+        ctx: Context<SplitCrateBps>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        child_keys: Vec<Pubkey>,
+        child_bps: Vec<u16>,
+    ) -> Result<()> {
+        let parent = &mut ctx.accounts.parent_crate;
+
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            parent.authority,
+            ErrorCode::UnauthorizedUpdate
+        );
+        parent.prepare_split_derivation(&ctx.accounts.config)?;
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::Split),
+            ErrorCode::OperationDisabled
+        );
+        require!(
+            !ctx.accounts.config.enforce_timestamp_order || timestamp >= parent.timestamp,
+            ErrorCode::TimestampBeforeParent
+        );
+
+        require!(child_keys.len() >= 2, ErrorCode::SplitRequiresMultipleChildren);
+        require!(child_keys.len() <= CrateRecord::MAX_CHILDREN, ErrorCode::TooManyChildren);
+
+        require!(
+            child_keys.len() == child_bps.len(),
+            ErrorCode::ChildKeyWeightMismatch
+        );
+
+        let total_bps: u32 = child_bps.iter().map(|bps| *bps as u32).sum();
+        require!(total_bps == BPS_DENOMINATOR, ErrorCode::InvalidBpsTotal);
+
+        let child_index = child_keys
+            .iter()
+            .position(|key| *key == ctx.accounts.crate_record.key())
+            .ok_or(ErrorCode::ChildNotInSplit)?;
+
+        let child_weights = ctx
+            .accounts
+            .config
+            .rounding_policy
+            .allocate(parent.weight, &child_bps);
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        let weight = child_weights[child_index];
+        parent.consume_into_split(&child_keys, &child_weights)?;
+        init_split_child(
+            &mut ctx.accounts.crate_record,
+            parent,
+            &ctx.accounts.config,
+            crate_id,
+            crate_did,
+            owner_did,
+            device_did,
+            location,
+            weight,
+            timestamp,
+            hash,
+            ipfs_cid,
+            ctx.accounts.authority.key(),
+            child_keys,
+            child_weights,
+            0,
+        )
+    }
+
+    /// Splits one crate with children given either as an absolute weight or as basis
+    /// points of what the absolute children leave. Percent shares must total 10000, and
+    /// without any the absolutes must add up to the parent weight.
+    pub fn split_crate_mixed_spec(
+        ctx: Context<SplitCrateMixedSpec>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        child_keys: Vec<Pubkey>,
+        child_specs: Vec<ChildSpec>,
+    ) -> Result<()> {
+        let parent = &mut ctx.accounts.parent_crate;
+
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            parent.authority,
+            ErrorCode::UnauthorizedUpdate
+        );
+        parent.prepare_split_derivation(&ctx.accounts.config)?;
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::Split),
+            ErrorCode::OperationDisabled
+        );
+        require!(
+            !ctx.accounts.config.enforce_timestamp_order || timestamp >= parent.timestamp,
+            ErrorCode::TimestampBeforeParent
+        );
+
+        require!(child_keys.len() >= 2, ErrorCode::SplitRequiresMultipleChildren);
+        require!(child_keys.len() <= CrateRecord::MAX_CHILDREN, ErrorCode::TooManyChildren);
+
+        require!(
+            child_keys.len() == child_specs.len(),
+            ErrorCode::ChildKeyWeightMismatch
+        );
+
+        let child_index = child_keys
+            .iter()
+            .position(|key| *key == ctx.accounts.crate_record.key())
+            .ok_or(ErrorCode::ChildNotInSplit)?;
+
+        let child_weights =
+            ChildSpec::resolve(&child_specs, parent.weight, ctx.accounts.config.rounding_policy)?;
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        let weight = child_weights[child_index];
+        parent.consume_into_split(&child_keys, &child_weights)?;
+        init_split_child(
+            &mut ctx.accounts.crate_record,
+            parent,
+            &ctx.accounts.config,
+            crate_id,
+            crate_did,
+            owner_did,
+            device_did,
+            location,
+            weight,
+            timestamp,
+            hash,
+            ipfs_cid,
+            ctx.accounts.authority.key(),
+            child_keys,
+            child_weights,
+            0,
+        )
+    }
+
+    /// Splits one crate among several new owners in one call. Each child in `recipients`
+    /// is created in the matching `remaining_accounts` entry (a fresh keypair signing the
+    /// transaction) and the parent is consumed, so the whole distribution lands or none of it.
+    pub fn split_to_many<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SplitToMany<'info>>,
+        location: String,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        recipients: Vec<SplitRecipient>,
+    ) -> Result<()> {
+        let parent = &mut ctx.accounts.parent_crate;
+
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            parent.authority,
+            ErrorCode::UnauthorizedUpdate
+        );
+        // Each recipient takes over its share of the goods
+        parent.prepare_transfer(&ctx.accounts.config)?;
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::Split),
+            ErrorCode::OperationDisabled
+        );
+        require!(
+            !ctx.accounts.config.enforce_timestamp_order || timestamp >= parent.timestamp,
+            ErrorCode::TimestampBeforeParent
+        );
+
+        require!(recipients.len() >= 2, ErrorCode::SplitRequiresMultipleChildren);
+        require!(recipients.len() <= CrateRecord::MAX_SPLIT_FANOUT, ErrorCode::SplitFanoutTooLarge);
+        require!(
+            ctx.remaining_accounts.len() == recipients.len(),
+            ErrorCode::SplitAccountMismatch
+        );
+
+        let mut total_child_weight: u32 = 0;
+        for recipient in recipients.iter() {
+            total_child_weight = total_child_weight
+                .checked_add(recipient.weight)
+                .ok_or(ErrorCode::WeightOverflow)?;
+        }
+        require!(total_child_weight == parent.weight, ErrorCode::SplitWeightMismatch);
+
+        if ctx.accounts.config.distinct_child_authorities {
+            for (i, recipient) in recipients.iter().enumerate() {
+                require!(
+                    !recipients[..i].iter().any(|other| other.authority == recipient.authority),
+                    ErrorCode::DuplicateChildAuthority
+                );
+            }
+        }
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        let child_keys: Vec<Pubkey> = ctx.remaining_accounts.iter().map(|info| *info.key).collect();
+        let child_weights: Vec<u32> = recipients.iter().map(|recipient| recipient.weight).collect();
+
+        for (child_info, recipient) in ctx.remaining_accounts.iter().zip(recipients) {
+            require!(
+                child_info.is_signer && child_info.is_writable,
+                ErrorCode::SplitAccountMismatch
+            );
+            create_crate_account(child_info, &ctx.accounts.authority, &ctx.accounts.system_program)?;
+
+            let mut record =
+                CrateRecord::try_deserialize_unchecked(&mut &child_info.try_borrow_data()?[..])?;
+            let handed_over = recipient.authority != parent.authority;
+            init_split_child(
+                &mut record,
+                parent,
+                &ctx.accounts.config,
+                recipient.crate_id,
+                String::new(),
+                String::new(),
+                String::new(),
+                location.clone(),
+                recipient.weight,
+                timestamp,
+                hash.clone(),
+                ipfs_cid.clone(),
+                recipient.authority,
+                child_keys.clone(),
+                child_weights.clone(),
+                0,
+            )?;
+            if handed_over {
+                record.transfer_count = record.transfer_count.saturating_add(1);
+            }
+            record.try_serialize(&mut &mut child_info.try_borrow_mut_data()?[..])?;
+        }
+
+        parent.consume_into_split(&child_keys, &child_weights)?;
+        require!(parent.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+
+        Ok(())
+    }
+
+    /// Carves `weight` off a parent that stays active with the remainder. The child is
+    /// appended to the parent's `child_crates` and links back to the parent in one call.
+    pub fn split_off(
+        ctx: Context<SplitOff>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        weight: u32,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+        carbon_grams: u64,
+    ) -> Result<()> {
+        let parent = &mut ctx.accounts.parent_crate;
+
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            parent.authority,
+            ErrorCode::UnauthorizedUpdate
+        );
+        parent.prepare_derivation(&ctx.accounts.config)?;
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::Split),
+            ErrorCode::OperationDisabled
+        );
+        require!(
+            !ctx.accounts.config.enforce_timestamp_order || timestamp >= parent.timestamp,
+            ErrorCode::TimestampBeforeParent
+        );
+        require!(weight > 0 && weight < parent.weight, ErrorCode::InvalidSplitRemainder);
+        require!(parent.child_crates.len() < CrateRecord::MAX_CHILDREN, ErrorCode::TooManyChildren);
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        init_split_child(
+            &mut ctx.accounts.crate_record,
+            parent,
+            &ctx.accounts.config,
+            crate_id,
+            crate_did,
+            owner_did,
+            device_did,
+            location,
+            weight,
+            timestamp,
+            hash,
+            ipfs_cid,
+            ctx.accounts.authority.key(),
+            Vec::new(),
+            Vec::new(),
+            carbon_grams,
+        )?;
+
+        parent.carbon_grams -= parent.carbon_share(weight);
+        parent.weight -= weight;
+        parent.push_child(ctx.accounts.crate_record.key(), weight);
+        // The split-off child points back at the parent, which can no longer be voided
+        parent.created_at = 0;
+        require!(parent.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+
+        Ok(())
+    }
+
+    /// Moves the goods of `parent_crate` into a new container record: a new `crate_id` and
+    /// container metadata, the same weight and contents. The parent is marked consumed.
+    pub fn repackage(
+        ctx: Context<Repackage>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        weight: u32,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+    ) -> Result<()> {
+        let parent = &mut ctx.accounts.parent_crate;
+
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            parent.authority,
+            ErrorCode::UnauthorizedUpdate
+        );
+        parent.prepare_derivation(&ctx.accounts.config)?;
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::Repackaged),
+            ErrorCode::OperationDisabled
+        );
+        require!(
+            !ctx.accounts.config.enforce_timestamp_order || timestamp >= parent.timestamp,
+            ErrorCode::TimestampBeforeParent
+        );
+        require!(weight == parent.weight, ErrorCode::RepackageWeightChanged);
+
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+
+        let record = &mut ctx.accounts.crate_record;
+        record.crate_id = crate_id;
+        record.crate_did = crate_did;
+        record.owner_did = owner_did;
+        record.device_did = device_did;
+        record.location = location;
+        record.weight = weight;
+        record.timestamp = timestamp;
+        record.hash = hash;
+        record.ipfs_cid = ipfs_cid;
+        record.authority = ctx.accounts.authority.key();
+        record.parent_crates = vec![parent.key()];
+        record.child_crates = Vec::new();
+        record.parent_weights = vec![parent.weight];
+        record.split_distribution = Vec::new();
+        record.operation_type = OperationType::Repackaged;
+        // Contents are unchanged, so everything describing them carries over
+        record.weight_unit = parent.weight_unit;
+        record.product_type = parent.product_type.clone();
+        record.certifications = parent.certifications.clone();
+        record.carbon_grams = parent.carbon_grams;
+        record.expiry = parent.expiry;
+        record.lot_code = parent.lot_code.clone();
+        record.excursion_flag = parent.excursion_flag;
+        record.handling_violation = parent.handling_violation;
+        record.max_tilt_degrees = parent.max_tilt_degrees;
+        record.max_stack_height = parent.max_stack_height;
+        record.dpp_id = parent.dpp_id.clone();
+        record.production_method = parent.production_method;
+        record.mixed_dpp_ids = parent.mixed_dpp_ids.clone();
+        record.display_decimals = parent.display_decimals;
+        record.supplier = parent.supplier;
+        record.transfer_count = parent.transfer_count;
+        record.min_hold_seconds = parent.hold_remaining_at(timestamp);
+        record.lineage_hash = lineage_hash(&record.hash, &[parent.lineage_hash]);
+        require!(record.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+        ctx.accounts.config.apply_operation_status(record, Clock::get()?.unix_timestamp)?;
+
+        parent.consume_into(record.key(), weight)?;
+        require!(parent.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+        Ok(())
+    }
+
+    /// Ancestors of the parent may be passed via `remaining_accounts` for cycle detection
+    pub fn update_parent_children<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateParent<'info>>,
+        child_keys: Vec<Pubkey>,
+    ) -> Result<()> {
+        let parent = &mut ctx.accounts.parent_crate;
+        require_keys_eq!(ctx.accounts.authority.key(), parent.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!parent.frozen, ErrorCode::CrateFrozen);
+        require!(child_keys.len() <= CrateRecord::MAX_CHILDREN, ErrorCode::TooManyChildren);
+        for child_key in child_keys.iter() {
+            require!(
+                *child_key != parent.key()
+                    && !ancestry_contains(&parent.parent_crates, *child_key, ctx.remaining_accounts)?,
+                ErrorCode::LineageCycleDetected
+            );
+        }
+        parent.consumed |= !child_keys.is_empty();
+        parent.child_crates = child_keys;
+        require!(parent.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+        Ok(())
+    }
+
+    /// Ancestors of `parent_key` may be passed via `remaining_accounts` for cycle detection
+    pub fn update_child_parent<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateChild<'info>>,
+        parent_key: Pubkey,
+    ) -> Result<()> {
+        let child = &mut ctx.accounts.child_crate;
+        require_keys_eq!(ctx.accounts.authority.key(), child.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!child.frozen, ErrorCode::CrateFrozen);
+        require!(
+            !ancestry_contains(&[parent_key], child.key(), ctx.remaining_accounts)?,
+            ErrorCode::LineageCycleDetected
+        );
+        if !child.parent_crates.contains(&parent_key) {
+            require!(child.parent_crates.len() < CrateRecord::MAX_PARENTS, ErrorCode::TooManyParents);
+            child.parent_crates.push(parent_key);
+            // Contribution of a retroactively linked parent is unknown
+            child.parent_weights.push(0);
+        }
+        require!(child.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+        Ok(())
+    }
+
+    /// Appends a mixed child to the `child_crates` of every parent in `remaining_accounts`.
+    /// Each parent must be listed in the child's `parent_crates` and be owned by the signer.
+    pub fn link_mix_children<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LinkMixChildren<'info>>,
+    ) -> Result<()> {
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+        let child = &ctx.accounts.child_crate;
+        for parent_info in ctx.remaining_accounts.iter() {
+            require!(child.parent_crates.contains(parent_info.key), ErrorCode::NotAParentOfChild);
+            let mut parent: Account<CrateRecord> = Account::try_from(parent_info)?;
+            require_keys_eq!(ctx.accounts.authority.key(), parent.authority, ErrorCode::UnauthorizedUpdate);
+            require!(!parent.frozen, ErrorCode::CrateFrozen);
+            if !parent.child_crates.contains(&child.key()) {
+                require!(parent.child_crates.len() < CrateRecord::MAX_CHILDREN, ErrorCode::TooManyChildren);
+                parent.child_crates.push(child.key());
+            }
+            parent.consumed = true;
+            require!(parent.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+            parent.exit(&crate::ID)?;
+        }
+        Ok(())
+    }
+
+    /// Returns just the weight via return data, for clients that can simulate but not decode accounts
+    pub fn get_weight(ctx: Context<ReadCrateRecord>) -> Result<u32> {
+        Ok(ctx.accounts.crate_record.weight)
+    }
+
+    /// Returns just the status via return data
+    pub fn get_status(ctx: Context<ReadCrateRecord>) -> Result<CrateStatus> {
+        Ok(ctx.accounts.crate_record.status)
+    }
+
+    /// Recomputes `carbon_intensity_mg_per_g` from the current carbon and weight. Call it
+    /// after either changes. Permissionless: the result is derived from on-chain data.
+    pub fn refresh_carbon_intensity(ctx: Context<RefreshCarbonIntensity>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        record.carbon_intensity_mg_per_g = record.carbon_intensity();
+        Ok(())
+    }
+
+    /// Returns every boolean flag of the crate packed as `CrateRecord::FLAG_*` bits
+    pub fn get_flags(ctx: Context<ReadCrateRecord>) -> Result<u32> {
+        Ok(ctx.accounts.crate_record.flags(Clock::get()?.unix_timestamp))
+    }
+
+    /// Removes duplicate lineage edges left by corrections (admin only). The first
+    /// occurrence of each key survives, together with its weight.
+    pub fn dedupe_lineage(ctx: Context<DedupeLineage>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require!(record.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+        let (parent_crates, parent_weights) = dedupe_edges(&record.parent_crates, &record.parent_weights);
+        let (child_crates, split_distribution) =
+            dedupe_edges(&record.child_crates, &record.split_distribution);
+        record.parent_crates = parent_crates;
+        record.parent_weights = parent_weights;
+        record.child_crates = child_crates;
+        record.split_distribution = split_distribution;
+        require!(record.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+        Ok(())
+    }
+
+    /// Recalls `root_crate` and every descendant passed via `remaining_accounts` (admin only).
+    /// The tree may arrive in any order; an account is recalled once it lists a recalled
+    /// parent, so accounts outside the tree are left untouched.
+    pub fn bulk_recall<'info>(ctx: Context<'_, '_, 'info, 'info, BulkRecall<'info>>) -> Result<()> {
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+        let root = &mut ctx.accounts.root_crate;
+        root.recalled = true;
+
+        let mut recalled = vec![root.key()];
+        let mut records: Vec<Account<CrateRecord>> = Vec::with_capacity(ctx.remaining_accounts.len());
+        for crate_info in ctx.remaining_accounts.iter() {
+            require_keys_eq!(*crate_info.owner, crate::ID, ErrorCode::NotProgramOwned);
+            if crate_info.key() != root.key() {
+                records.push(Account::try_from(crate_info)?);
+            }
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for record in records.iter_mut() {
+                if !record.recalled && record.parent_crates.iter().any(|key| recalled.contains(key)) {
+                    record.recalled = true;
+                    recalled.push(record.key());
+                    changed = true;
+                }
+            }
+        }
+        for record in records.iter().filter(|record| recalled.contains(&record.key())) {
+            record.exit(&crate::ID)?;
+        }
+        Ok(())
+    }
+
+    /// Asserts a batch conserves mass: the first `input_count` crates in `remaining_accounts`
+    /// are raw-material inputs, the rest are outputs, and
+    /// `sum(outputs) + declared_loss == sum(inputs)` in grams. Read-only.
+    pub fn audit_batch_conservation<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AuditBatchConservation<'info>>,
+        input_count: u8,
+        declared_loss: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+        let input_count = input_count as usize;
+        require!(input_count <= ctx.remaining_accounts.len(), ErrorCode::BatchConservationFailed);
+
+        let mut seen: Vec<Pubkey> = Vec::with_capacity(ctx.remaining_accounts.len());
+        let (mut input_grams, mut output_grams) = (0u64, 0u64);
+        for (i, crate_info) in ctx.remaining_accounts.iter().enumerate() {
+            // A crate counted twice could balance any batch
+            require!(!seen.contains(crate_info.key), ErrorCode::BatchConservationFailed);
+            seen.push(crate_info.key());
+            let record: Account<CrateRecord> = Account::try_from(crate_info)?;
+            let grams = record.weight_unit.to_grams(record.weight);
+            if i < input_count {
+                input_grams = input_grams.checked_add(grams).ok_or(ErrorCode::WeightOverflow)?;
+            } else {
+                output_grams = output_grams.checked_add(grams).ok_or(ErrorCode::WeightOverflow)?;
+            }
+        }
+        require!(
+            output_grams.checked_add(declared_loss) == Some(input_grams),
+            ErrorCode::BatchConservationFailed
+        );
+        Ok(())
+    }
+
+    /// Returns the total weight in grams of `owner`'s crates in `remaining_accounts` via
+    /// return data. Voided (closed), burned and tombstoned crates are skipped. Read-only.
+    pub fn compute_owner_inventory<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ComputeOwnerInventory<'info>>,
+        owner: Pubkey,
+    ) -> Result<u64> {
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+        let mut seen: Vec<Pubkey> = Vec::with_capacity(ctx.remaining_accounts.len());
+        let mut total_grams = 0u64;
+        for crate_info in ctx.remaining_accounts.iter() {
+            require!(!seen.contains(crate_info.key), ErrorCode::DuplicateAccount);
+            seen.push(crate_info.key());
+            if crate_info.data_is_empty() {
+                continue;
+            }
+            let record: Account<CrateRecord> = Account::try_from(crate_info)?;
+            require_keys_eq!(record.authority, owner, ErrorCode::AuthorityMismatch);
+            if record.duplicate_of != Pubkey::default() || record.burned {
+                continue;
+            }
+            total_grams = total_grams
+                .checked_add(record.weight_unit.to_grams(record.weight))
+                .ok_or(ErrorCode::WeightOverflow)?;
+        }
+        Ok(total_grams)
+    }
+
+    /// Returns, via return data, sha256 over `key || weight (LE) || operation_type` of each
+    /// crate in `remaining_accounts`, in the order given. Indexers compare it against their
+    /// own digest to detect drift. Read-only.
+    pub fn compute_index_digest<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ComputeIndexDigest<'info>>,
+    ) -> Result<[u8; 32]> {
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+        let mut entries: Vec<u8> = Vec::with_capacity(ctx.remaining_accounts.len() * 37);
+        for crate_info in ctx.remaining_accounts.iter() {
+            require_keys_eq!(*crate_info.owner, crate::ID, ErrorCode::NotProgramOwned);
+            let record: Account<CrateRecord> = Account::try_from(crate_info)?;
+            entries.extend_from_slice(crate_info.key.as_ref());
+            entries.extend_from_slice(&record.weight.to_le_bytes());
+            entries.push(record.operation_type as u8);
+        }
+        Ok(hashv(&[&entries]).to_bytes())
+    }
+
+    /// Recomputes `lineage_hash` from the current parents, passed in `parent_crates` order
+    /// via `remaining_accounts`. A mismatch means the parent set changed after creation:
+    /// the record is flagged `tampered` and `TamperDetected` is emitted.
+    pub fn verify_tamper<'info>(
+        ctx: Context<'_, '_, 'info, 'info, VerifyTamper<'info>>,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            ctx.remaining_accounts.len() == record.parent_crates.len(),
+            ErrorCode::ParentSetMismatch
+        );
+        let mut parent_hashes = Vec::new();
+        for (parent_info, expected) in ctx.remaining_accounts.iter().zip(record.parent_crates.iter()) {
+            require_keys_eq!(*parent_info.key, *expected, ErrorCode::ParentSetMismatch);
+            let parent: Account<CrateRecord> = Account::try_from(parent_info)?;
+            parent_hashes.push(parent.lineage_hash);
+        }
+
+        let expected_hash = lineage_hash(&record.hash, &parent_hashes);
+        if expected_hash != record.lineage_hash {
+            record.tampered = true;
+            emit!(TamperDetected {
+                authority: record.authority,
+                crate_record: record.key(),
+                program_event_version: PROGRAM_EVENT_VERSION,
+                stored_lineage_hash: record.lineage_hash,
+                expected_lineage_hash: expected_hash,
+            });
+        }
+        Ok(())
+    }
+
+    /// Recomputes `cached_parent_weight_sum` from the parents' current weights in grams,
+    /// passed in `parent_crates` order via `remaining_accounts`
+    pub fn refresh_parent_sum<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RefreshParentSum<'info>>,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            ctx.remaining_accounts.len() == record.parent_crates.len(),
+            ErrorCode::ParentSetMismatch
+        );
+        let mut total_grams: u64 = 0;
+        for (parent_info, expected) in ctx.remaining_accounts.iter().zip(record.parent_crates.iter()) {
+            require_keys_eq!(*parent_info.key, *expected, ErrorCode::ParentSetMismatch);
+            let parent: Account<CrateRecord> = Account::try_from(parent_info)?;
+            total_grams += parent.weight_unit.to_grams(parent.weight);
+        }
+        record.cached_parent_weight_sum = u32::try_from(total_grams).map_err(|_| ErrorCode::WeightOverflow)?;
+        Ok(())
+    }
+
+    /// Flags the crate `stale` once its `timestamp` is older than `staleness_threshold`.
+    /// Permissionless so warehouse bots can sweep inventory.
+    pub fn check_staleness(ctx: Context<CheckStaleness>) -> Result<()> {
+        let threshold = ctx.accounts.config.staleness_threshold;
+        let record = &mut ctx.accounts.crate_record;
+        // Timestamps are caller-supplied, so the age must not overflow on extreme values
+        let age = Clock::get()?.unix_timestamp.saturating_sub(record.timestamp);
+        record.stale = threshold > 0 && age > threshold as i64;
+        if record.stale {
+            emit!(StaleInventoryFlagged {
+                authority: record.authority,
+                crate_record: record.key(),
+                program_event_version: PROGRAM_EVENT_VERSION,
+                age,
+                threshold,
+            });
+        }
+        Ok(())
+    }
+
+    /// Burns the crate once past its expiry when `auto_burn_on_expiry` is set: it is
+    /// frozen and marked `burned`, so it can no longer be used as a parent. Permissionless.
+    pub fn check_expiry(ctx: Context<CheckExpiry>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        let expired = record.is_expired(Clock::get()?.unix_timestamp);
+        if ctx.accounts.config.auto_burn_on_expiry && expired && !record.burned {
+            record.burned = true;
+            record.frozen = true;
+            emit!(AutoBurned {
+                authority: record.authority,
+                crate_record: record.key(),
+                program_event_version: PROGRAM_EVENT_VERSION,
+                expiry: record.expiry,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns this split child's share of its parent in basis points
+    pub fn get_split_share_bps(ctx: Context<ReadCrateRecord>) -> Result<u16> {
+        let record = &ctx.accounts.crate_record;
+        let index = record
+            .child_crates
+            .iter()
+            .position(|key| *key == record.key())
+            .filter(|index| *index < record.split_distribution.len())
+            .ok_or(ErrorCode::ChildNotInDistribution)?;
+        let total: u64 = record.split_distribution.iter().map(|w| *w as u64).sum();
+        require!(total > 0, ErrorCode::ChildNotInDistribution);
+        let share = record.split_distribution[index] as u64 * BPS_DENOMINATOR as u64 / total;
+        Ok(share as u16)
+    }
+
+    /// Read-only check that the record's lineage vectors are consistent
+    pub fn verify_lineage_invariants(ctx: Context<VerifyLineageInvariants>) -> Result<()> {
+        require!(
+            ctx.accounts.crate_record.lineage_invariants_hold(),
+            ErrorCode::LineageInvariantBroken
+        );
+        Ok(())
+    }
+
+    /// Marks an off-chain operation id as processed for the signing authority.
+    /// Ids are bucketed into bitmap pages of `ProcessedBitmap::IDS_PER_PAGE`.
+    pub fn mark_processed(ctx: Context<MarkProcessed>, operation_id: u64) -> Result<()> {
+        let bitmap = &mut ctx.accounts.processed_bitmap;
+        let page = operation_id / ProcessedBitmap::IDS_PER_PAGE;
+        if bitmap.authority == Pubkey::default() {
+            bitmap.authority = ctx.accounts.authority.key();
+            bitmap.page = page;
+        }
+
+        let bit = (operation_id % ProcessedBitmap::IDS_PER_PAGE) as usize;
+        let mask = 1u8 << (bit % 8);
+        require!(bitmap.bits[bit / 8] & mask == 0, ErrorCode::AlreadyProcessed);
+        bitmap.bits[bit / 8] |= mask;
+        Ok(())
+    }
+
+    /// Creates the program-wide config; the signer becomes its admin
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.rounding_policy = RoundingPolicy::LastChild;
+        Ok(())
+    }
+
+    /// Applies every `Some` field of `update` to the config (admin only)
+    pub fn update_config(ctx: Context<UpdateConfig>, update: ConfigUpdate) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        if let Some(admin) = update.admin {
+            config.admin = admin;
+        }
+        if let Some(rounding_policy) = update.rounding_policy {
+            config.rounding_policy = rounding_policy;
+        }
+        if let Some(disabled_operations) = update.disabled_operations {
+            config.disabled_operations = disabled_operations;
+        }
+        if let Some(registered_labs) = update.registered_labs {
+            require!(
+                registered_labs.len() <= ProgramConfig::MAX_LABS,
+                ErrorCode::TooManyRegisteredLabs
+            );
+            config.registered_labs = registered_labs;
+        }
+        if let Some(strict_hash_uniqueness) = update.strict_hash_uniqueness {
+            config.strict_hash_uniqueness = strict_hash_uniqueness;
+        }
+        if let Some(enforce_timestamp_order) = update.enforce_timestamp_order {
+            config.enforce_timestamp_order = enforce_timestamp_order;
+        }
+        if let Some(transfer_tolerance_bps) = update.transfer_tolerance_bps {
+            require!(
+                transfer_tolerance_bps as u32 <= BPS_DENOMINATOR,
+                ErrorCode::InvalidTolerance
+            );
+            config.transfer_tolerance_bps = transfer_tolerance_bps;
+        }
+        if let Some(drift_alert_threshold) = update.drift_alert_threshold {
+            config.drift_alert_threshold = drift_alert_threshold;
+        }
+        if let Some(require_certification) = update.require_certification_for_transfer {
+            config.require_certification_for_transfer = require_certification;
+        }
+        if let Some(max_batch_accounts) = update.max_batch_accounts {
+            config.max_batch_accounts = max_batch_accounts;
+        }
+        if let Some(strict_transfer_content) = update.strict_transfer_content {
+            config.strict_transfer_content = strict_transfer_content;
+        }
+        if let Some(mix_tolerance_bps) = update.mix_tolerance_bps {
+            require!(
+                mix_tolerance_bps as u32 <= BPS_DENOMINATOR,
+                ErrorCode::InvalidTolerance
+            );
+            config.mix_tolerance_bps = mix_tolerance_bps;
+        }
+        if let Some(void_window_secs) = update.void_window_secs {
+            config.void_window_secs = void_window_secs;
+        }
+        if let Some(max_expiry_spread) = update.max_expiry_spread {
+            config.max_expiry_spread = max_expiry_spread;
+        }
+        if let Some(strict_expiry_spread) = update.strict_expiry_spread {
+            config.strict_expiry_spread = strict_expiry_spread;
+        }
+        if let Some(customs_authorities) = update.customs_authorities {
+            require!(
+                customs_authorities.len() <= ProgramConfig::MAX_CUSTOMS_AUTHORITIES,
+                ErrorCode::TooManyCustomsAuthorities
+            );
+            config.customs_authorities = customs_authorities;
+        }
+        if let Some(vault_controllers) = update.vault_controllers {
+            require!(
+                vault_controllers.len() <= ProgramConfig::MAX_VAULT_CONTROLLERS,
+                ErrorCode::TooManyVaultControllers
+            );
+            config.vault_controllers = vault_controllers;
+        }
+        if let Some(strict_split_order) = update.strict_split_order {
+            config.strict_split_order = strict_split_order;
+        }
+        if let Some(staleness_threshold) = update.staleness_threshold {
+            config.staleness_threshold = staleness_threshold;
+        }
+        if let Some(rate_limit_window) = update.rate_limit_window {
+            config.rate_limit_window = rate_limit_window;
+        }
+        if let Some(max_ops_per_window) = update.max_ops_per_window {
+            require!(
+                max_ops_per_window as usize <= RateLimit::MAX_OPS,
+                ErrorCode::InvalidRateLimit
+            );
+            config.max_ops_per_window = max_ops_per_window;
+        }
+        if let Some(required_fields) = update.required_fields {
+            config.required_fields = required_fields;
+        }
+        if let Some(mix_requires_same_lot) = update.mix_requires_same_lot {
+            config.mix_requires_same_lot = mix_requires_same_lot;
+        }
+        if let Some(auto_burn_on_expiry) = update.auto_burn_on_expiry {
+            config.auto_burn_on_expiry = auto_burn_on_expiry;
+        }
+        if let Some(high_value_threshold) = update.high_value_threshold {
+            config.high_value_threshold = high_value_threshold;
+        }
+        if let Some(high_value_approvers) = update.high_value_approvers {
+            require!(
+                high_value_approvers.len() <= ProgramConfig::MAX_HIGH_VALUE_APPROVERS,
+                ErrorCode::TooManyHighValueApprovers
+            );
+            config.high_value_approvers = high_value_approvers;
+        }
+        if let Some(allowed_cid_prefixes) = update.allowed_cid_prefixes {
+            require!(
+                allowed_cid_prefixes.len() <= ProgramConfig::MAX_CID_PREFIXES
+                    && allowed_cid_prefixes
+                        .iter()
+                        .all(|prefix| !prefix.is_empty() && prefix.len() <= ProgramConfig::MAX_CID_PREFIX_LEN),
+                ErrorCode::InvalidCidPrefixes
+            );
+            config.allowed_cid_prefixes = allowed_cid_prefixes;
+        }
+        if let Some(distinct_child_authorities) = update.distinct_child_authorities {
+            config.distinct_child_authorities = distinct_child_authorities;
+        }
+        if let Some(max_transfers) = update.max_transfers {
+            config.max_transfers = max_transfers;
+        }
+        if let Some(operation_statuses) = update.operation_statuses {
+            // New records start out Registered, so mapping to it would always fail
+            require!(
+                operation_statuses.len() <= ProgramConfig::MAX_OPERATION_STATUSES
+                    && operation_statuses.iter().enumerate().all(|(i, entry)| {
+                        entry.status != CrateStatus::Registered
+                            && operation_statuses[..i].iter().all(|other| other.operation != entry.operation)
+                    }),
+                ErrorCode::InvalidOperationStatuses
+            );
+            config.operation_statuses = operation_statuses;
+        }
+        if let Some(handoff_tolerance_meters) = update.handoff_tolerance_meters {
+            config.handoff_tolerance_meters = handoff_tolerance_meters;
+        }
+        if let Some(require_id_reservation) = update.require_id_reservation {
+            config.require_id_reservation = require_id_reservation;
+        }
+        if let Some(reservation_ttl_secs) = update.reservation_ttl_secs {
+            config.reservation_ttl_secs = reservation_ttl_secs;
+        }
+        if let Some(require_inspection) = update.require_inspection_for_transfer {
+            config.require_inspection_for_transfer = require_inspection;
+        }
+        if let Some(registered_scales) = update.registered_scales {
+            require!(
+                registered_scales.len() <= ProgramConfig::MAX_SCALES,
+                ErrorCode::TooManyRegisteredScales
+            );
+            config.registered_scales = registered_scales;
+        }
+        Ok(())
+    }
+
+    /// Caps the total grams `authority` may create; 0 removes the cap (admin only)
+    pub fn set_production_quota(
+        ctx: Context<SetProductionQuota>,
+        authority: Pubkey,
+        production_quota: u64,
+    ) -> Result<()> {
+        let production = &mut ctx.accounts.production;
+        production.authority = authority;
+        production.production_quota = production_quota;
+        Ok(())
+    }
+
+    /// Emergency lockdown: freezes every crate in `remaining_accounts` (admin only).
+    /// All crates must belong to `target_authority`.
+    pub fn freeze_by_authority<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FreezeByAuthority<'info>>,
+        target_authority: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+        for crate_info in ctx.remaining_accounts.iter() {
+            let mut record: Account<CrateRecord> = Account::try_from(crate_info)?;
+            require_keys_eq!(record.authority, target_authority, ErrorCode::AuthorityMismatch);
+            record.frozen = true;
+            record.exit(&crate::ID)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the crate to a new operational status and appends it to the history
+    pub fn update_status(ctx: Context<UpdateStatus>, status: CrateStatus) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        require!(record.status != CrateStatus::Quarantined, ErrorCode::CrateQuarantined);
+        record.set_status(status, Clock::get()?.unix_timestamp)
+    }
+
+    /// Holds a crate pending inspection; the authority or a registered inspector may do it.
+    /// Transfers and status changes are blocked until `release_quarantine`.
+    pub fn quarantine_crate(ctx: Context<Quarantine>) -> Result<()> {
+        let signer = ctx.accounts.signer.key();
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            signer == record.authority || ctx.accounts.config.registered_labs.contains(&signer),
+            ErrorCode::UnauthorizedUpdate
+        );
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        record.set_status(CrateStatus::Quarantined, Clock::get()?.unix_timestamp)
+    }
+
+    /// Clears a quarantine after inspection, returning the crate to `Received`.
+    /// Only a registered inspector may release.
+    pub fn release_quarantine(ctx: Context<Quarantine>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            ctx.accounts.config.registered_labs.contains(&ctx.accounts.signer.key()),
+            ErrorCode::UnauthorizedUpdate
+        );
+        require!(record.status == CrateStatus::Quarantined, ErrorCode::CrateNotQuarantined);
+        // A full status history must not keep the crate quarantined forever
+        record.force_status(CrateStatus::Received, Clock::get()?.unix_timestamp)
+    }
+
+    /// Records a weighing signed by a registered scale. The preceding instruction
+    /// must be an Ed25519 verification of `scale_pubkey` over `weighing_message`.
+    pub fn record_weighing(
+        ctx: Context<RecordWeighing>,
+        scale_pubkey: Pubkey,
+        weight: u32,
+        timestamp: i64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.config.registered_scales.contains(&scale_pubkey),
+            ErrorCode::UnregisteredScale
+        );
+
+        let record = &mut ctx.accounts.crate_record;
+        require!(weight == record.weight, ErrorCode::WeighingWeightMismatch);
+
+        let message = weighing_message(&record.crate_id, weight, timestamp);
+        verify_ed25519_signature(&ctx.accounts.instructions, &scale_pubkey, &message)?;
+
+        record.weighed_by = scale_pubkey;
+        record.weight_verified = true;
+        Ok(())
+    }
+
+    /// Records the receiver's own weighing of an incoming crate. The recorded weight is
+    /// left alone; a delta beyond `transfer_tolerance_bps` sets `receiving_discrepancy`
+    /// and emits `ReceivingDiscrepancy` for reconciliation.
+    pub fn record_receiving_check(ctx: Context<RecordReceivingCheck>, measured_weight: u32) -> Result<()> {
+        let receiver = ctx.accounts.receiver.key();
+        let now = Clock::get()?.unix_timestamp;
+        let record = &mut ctx.accounts.crate_record;
+        record.require_authority_or_delegate(&receiver, now)?;
+
+        let delta = measured_weight as i64 - record.weight as i64;
+        let discrepancy = !ctx.accounts.config.within_transfer_tolerance(record.weight, measured_weight);
+        record.receiving_check = Some(ReceivingCheck {
+            receiver,
+            measured_weight,
+            delta,
+            timestamp: now,
+        });
+        record.receiving_discrepancy = discrepancy;
+
+        if discrepancy {
+            emit!(ReceivingDiscrepancy {
+                authority: record.authority,
+                crate_record: record.key(),
+                program_event_version: PROGRAM_EVENT_VERSION,
+                recorded_weight: record.weight,
+                measured_weight,
+                delta,
+            });
+        }
+        Ok(())
+    }
+
+    /// Corrects a miscalibrated weight, signed by the authority or the config admin.
+    /// The prior weight and `reason_cid` justification are logged and sealed crates are
+    /// rejected. A split child's own entry in `split_distribution` follows the correction.
+    pub fn correct_weight(ctx: Context<CorrectWeight>, new_weight: u32, reason_cid: String) -> Result<()> {
+        let corrected_by = ctx.accounts.signer.key();
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            corrected_by == record.authority || corrected_by == ctx.accounts.config.admin,
+            ErrorCode::UnauthorizedUpdate
+        );
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        require!(
+            !reason_cid.is_empty() && reason_cid.len() <= CrateRecord::MAX_CID_LEN,
+            ErrorCode::CorrectionReasonRequired
+        );
+        require!(
+            record.weight_corrections.len() < CrateRecord::MAX_WEIGHT_CORRECTIONS,
+            ErrorCode::TooManyWeightCorrections
+        );
+
+        let prior_weight = record.weight;
+        record.weight_corrections.push(WeightCorrection {
+            prior_weight,
+            delta: new_weight as i64 - prior_weight as i64,
+            reason_cid,
+            corrected_by,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        let key = record.key();
+        record.set_weight(key, new_weight)
+    }
+
+    /// Removes a QA sample from the crate, signed by its authority or a registered lab.
+    /// Like a correction, the scale verification is dropped and a split child's own entry
+    /// in `split_distribution` follows the new weight.
+    pub fn take_sample(ctx: Context<TakeSample>, sample_weight: u32) -> Result<()> {
+        let sampler = ctx.accounts.sampler.key();
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            sampler == record.authority || ctx.accounts.config.registered_labs.contains(&sampler),
+            ErrorCode::UnauthorizedSampler
+        );
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        require!(
+            sample_weight > 0 && sample_weight < record.weight,
+            ErrorCode::InvalidSampleWeight
+        );
+        require!(record.samples.len() < CrateRecord::MAX_SAMPLES, ErrorCode::TooManySamples);
+
+        record.samples.push(SampleEntry {
+            sampler,
+            sample_weight,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        let (key, weight) = (record.key(), record.weight - sample_weight);
+        record.set_weight(key, weight)
+    }
+
+    /// Records that the crate cleared customs at `port`; signed by a registered customs
+    /// authority. One clearance per border crossed.
+    pub fn record_customs_clearance(
+        ctx: Context<RecordCustomsClearance>,
+        port: String,
+        clearance_doc_cid: String,
+    ) -> Result<()> {
+        let cleared_by = ctx.accounts.customs_authority.key();
+        require!(
+            ctx.accounts.config.customs_authorities.contains(&cleared_by),
+            ErrorCode::NotCustomsAuthority
+        );
+        require!(
+            port.len() <= CustomsClearance::MAX_PORT_LEN && clearance_doc_cid.len() <= CrateRecord::MAX_CID_LEN,
+            ErrorCode::CustomsFieldTooLong
+        );
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            record.customs_clearances.len() < CrateRecord::MAX_CUSTOMS_CLEARANCES,
+            ErrorCode::TooManyCustomsClearances
+        );
+        record.customs_clearances.push(CustomsClearance {
+            port,
+            cleared_by,
+            clearance_doc_cid,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    /// Folds the `lineage_hash` of the crate and its ancestors (via `remaining_accounts`)
+    /// into a Merkle root. Every ancestor must be a parent of the crate or of another
+    /// supplied ancestor.
+    pub fn compute_provenance_root<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ComputeProvenanceRoot<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.remaining_accounts.len() <= MAX_LINEAGE_HOPS,
+            ErrorCode::TooManyProvenanceAncestors
+        );
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+
+        let record = &ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(record.edge_disputes.is_empty(), ErrorCode::EdgeDisputed);
+
+        let mut ancestors: Vec<Account<CrateRecord>> = Vec::new();
+        for ancestor_info in ctx.remaining_accounts.iter() {
+            ancestors.push(Account::try_from(ancestor_info)?);
+        }
+        for ancestor in ancestors.iter() {
+            let key = ancestor.key();
+            require!(
+                record.parent_crates.contains(&key)
+                    || ancestors.iter().any(|other| other.parent_crates.contains(&key)),
+                ErrorCode::NotAnAncestor
+            );
+        }
+
+        let mut leaves: Vec<[u8; 32]> = ancestors.iter().map(|a| a.lineage_hash).collect();
+        leaves.push(record.lineage_hash);
+        let root = merkle_root(leaves);
+
+        ctx.accounts.crate_record.provenance_root = root;
+        Ok(())
+    }
+
+    /// Checks `leaf` is included under the crate's stored `provenance_root`. Bit `i` of
+    /// `directions` is set when `proof[i]` is the left sibling at that level; levels where
+    /// the node was promoted unpaired have no entry. Read-only.
+    pub fn verify_merkle_proof(
+        ctx: Context<VerifyMerkleProof>,
+        leaf: [u8; 32],
+        proof: Vec<[u8; 32]>,
+        directions: u32,
+    ) -> Result<()> {
+        require!(proof.len() <= MAX_MERKLE_PROOF_LEN, ErrorCode::InvalidMerkleProof);
+        let root = ctx.accounts.crate_record.provenance_root;
+        require!(
+            root != [0u8; 32] && merkle_root_from_proof(leaf, &proof, directions) == root,
+            ErrorCode::InvalidMerkleProof
+        );
+        Ok(())
+    }
+
+    /// Clean bill of health: succeeds only if neither the crate nor any of its ancestors
+    /// in `remaining_accounts` is recalled. Every ancestor must be a parent of the crate
+    /// or of another supplied ancestor. Read-only.
+    pub fn assert_not_recalled<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AssertNotRecalled<'info>>,
+    ) -> Result<()> {
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+        let record = &ctx.accounts.crate_record;
+
+        let mut ancestors: Vec<Account<CrateRecord>> = Vec::new();
+        for ancestor_info in ctx.remaining_accounts.iter() {
+            ancestors.push(Account::try_from(ancestor_info)?);
+        }
+        for ancestor in ancestors.iter() {
+            let key = ancestor.key();
+            require!(
+                record.parent_crates.contains(&key)
+                    || ancestors.iter().any(|other| other.parent_crates.contains(&key)),
+                ErrorCode::NotAnAncestor
+            );
+        }
+
+        for checked in std::iter::once(record).chain(ancestors.iter()) {
+            if checked.recalled {
+                msg!("Recalled crate: {}", checked.key());
+                return err!(ErrorCode::CrateIsRecalled);
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates an empty shipment (pallet) grouping crates of the signing authority
+    pub fn create_shipment(ctx: Context<CreateShipment>, shipment_id: String) -> Result<()> {
+        let shipment = &mut ctx.accounts.shipment;
+        shipment.authority = ctx.accounts.authority.key();
+        shipment.shipment_id = shipment_id;
+        shipment.crate_ids = Vec::new();
+        Ok(())
+    }
+
+    /// Opens a purchase order expecting `expected_grams` from `supplier`
+    pub fn create_purchase_order(
+        ctx: Context<CreatePurchaseOrder>,
+        po_number: String,
+        supplier: Pubkey,
+        expected_grams: u64,
+    ) -> Result<()> {
+        let po = &mut ctx.accounts.purchase_order;
+        po.authority = ctx.accounts.authority.key();
+        po.po_number = po_number;
+        po.supplier = supplier;
+        po.expected_grams = expected_grams;
+        po.fulfilled_grams = 0;
+        Ok(())
+    }
+
+    /// Binds a supplier's crate to the purchase order and counts its weight as fulfilled
+    pub fn fulfill_po(ctx: Context<FulfillPo>) -> Result<()> {
+        let po = &mut ctx.accounts.purchase_order;
+        let record = &mut ctx.accounts.crate_record;
+        let authority = ctx.accounts.authority.key();
+        require_keys_eq!(authority, record.authority, ErrorCode::UnauthorizedUpdate);
+        require_keys_eq!(authority, po.supplier, ErrorCode::UnauthorizedUpdate);
+        require!(record.po_number.is_empty(), ErrorCode::CrateAlreadyOnPo);
+
+        po.fulfilled_grams = po
+            .fulfilled_grams
+            .saturating_add(record.weight_unit.to_grams(record.weight));
+        require!(po.fulfilled_grams <= po.expected_grams, ErrorCode::PoOverfulfilled);
+        record.po_number = po.po_number.clone();
+        Ok(())
+    }
+
+    /// Creates an empty pool for fungibly stored bulk goods (e.g. a silo)
+    pub fn create_pool(ctx: Context<CreatePool>, pool_id: String) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.authority = ctx.accounts.authority.key();
+        pool.pool_id = pool_id;
+        pool.weight = 0;
+        Ok(())
+    }
+
+    /// Consumes a crate into the pool: its weight joins the pool and the crate is frozen
+    pub fn deposit_to_pool(ctx: Context<DepositToPool>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let record = &mut ctx.accounts.crate_record;
+        let authority = ctx.accounts.authority.key();
+        require_keys_eq!(authority, pool.authority, ErrorCode::UnauthorizedUpdate);
+        require_keys_eq!(authority, record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        require!(record.escrow.is_none(), ErrorCode::EscrowPending);
+        require!(record.spl_mint.is_none(), ErrorCode::CrateTokenized);
+        require!(record.status != CrateStatus::Quarantined, ErrorCode::CrateQuarantined);
+        require!(!record.consumed, ErrorCode::CrateConsumed);
+
+        pool.weight = pool
+            .weight
+            .checked_add(record.weight_unit.to_grams(record.weight))
+            .ok_or(ErrorCode::WeightOverflow)?;
+        record.pool = Some(pool.key());
+        record.frozen = true;
+        record.consumed = true;
+        Ok(())
+    }
+
+    /// Carves a new crate of `weight` grams out of the pool. Pooled goods are fungible,
+    /// so the crate has no parents; `pool` records where it came from.
+    pub fn withdraw_from_pool(
+        ctx: Context<WithdrawFromPool>,
+        crate_id: String,
+        crate_did: String,
+        owner_did: String,
+        device_did: String,
+        location: String,
+        weight: u32,
+        timestamp: i64,
+        hash: String,
+        ipfs_cid: String,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        require_keys_eq!(ctx.accounts.authority.key(), pool.authority, ErrorCode::UnauthorizedUpdate);
+        require!(
+            ctx.accounts.config.is_operation_enabled(OperationType::PoolWithdrawal),
+            ErrorCode::OperationDisabled
+        );
+        require!(weight as u64 <= pool.weight, ErrorCode::InsufficientPoolBalance);
+        require_document_refs(&ctx.accounts.config, &hash, &ipfs_cid)?;
+        pool.weight -= weight as u64;
+
+        let record = &mut ctx.accounts.crate_record;
+        record.crate_id = crate_id;
+        record.crate_did = crate_did;
+        record.owner_did = owner_did;
+        record.device_did = device_did;
+        record.location = location;
+        record.weight = weight;
+        record.timestamp = timestamp;
+        record.hash = hash;
+        record.ipfs_cid = ipfs_cid;
+        record.authority = ctx.accounts.authority.key();
+        record.parent_crates = Vec::new();
+        record.child_crates = Vec::new();
+        record.parent_weights = Vec::new();
+        record.split_distribution = Vec::new();
+        record.operation_type = OperationType::PoolWithdrawal;
+        record.weight_unit = WeightUnit::Grams;
+        record.pool = Some(pool.key());
+        record.lineage_hash = lineage_hash(&record.hash, &[]);
+        let now = Clock::get()?.unix_timestamp;
+        record.created_at = now;
+        ctx.accounts.config.apply_operation_status(record, now)
+    }
+
+    pub fn add_crate_to_shipment(ctx: Context<UpdateShipmentMembers>) -> Result<()> {
+        let shipment = &mut ctx.accounts.shipment;
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), shipment.authority, ErrorCode::UnauthorizedUpdate);
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(record.shipment.is_none(), ErrorCode::CrateAlreadyInShipment);
+        require!(shipment.crate_ids.len() < Shipment::MAX_CRATES, ErrorCode::ShipmentFull);
+
+        shipment.crate_ids.push(record.key());
+        record.shipment = Some(shipment.key());
+        Ok(())
+    }
+
+    pub fn remove_crate_from_shipment(ctx: Context<UpdateShipmentMembers>) -> Result<()> {
+        let shipment = &mut ctx.accounts.shipment;
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), shipment.authority, ErrorCode::UnauthorizedUpdate);
+        require!(record.shipment == Some(shipment.key()), ErrorCode::CrateNotInShipment);
+
+        shipment.crate_ids.retain(|key| *key != record.key());
+        record.shipment = None;
+        Ok(())
+    }
+
+    /// Hands the shipment and every member crate to `new_authority` in one call.
+    /// All members must be passed via `remaining_accounts`.
+    pub fn transfer_shipment<'info>(
+        ctx: Context<'_, '_, 'info, 'info, TransferShipment<'info>>,
+        new_authority: Pubkey,
+    ) -> Result<()> {
+        let shipment = &mut ctx.accounts.shipment;
+        require_keys_eq!(ctx.accounts.authority.key(), shipment.authority, ErrorCode::UnauthorizedUpdate);
+        require!(
+            ctx.remaining_accounts.len() == shipment.crate_ids.len(),
+            ErrorCode::ShipmentMembersMismatch
+        );
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+
+        let mut seen: Vec<Pubkey> = Vec::new();
+        for crate_info in ctx.remaining_accounts.iter() {
+            let mut record: Account<CrateRecord> = Account::try_from(crate_info)?;
+            require!(
+                record.shipment == Some(shipment.key())
+                    && shipment.crate_ids.contains(&record.key())
+                    && !seen.contains(&record.key()),
+                ErrorCode::ShipmentMembersMismatch
+            );
+            seen.push(record.key());
+            record.hand_over(new_authority, &ctx.accounts.config)?;
+            record.exit(&crate::ID)?;
+        }
+
+        shipment.authority = new_authority;
+        Ok(())
+    }
+
+    /// Rolls the member crates in `remaining_accounts` up into one status: the least
+    /// advanced member (a quarantined one counts as least advanced), and whether every
+    /// member shares it. Read-only.
+    pub fn compute_shipment_status<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ComputeShipmentStatus<'info>>,
+    ) -> Result<ShipmentStatus> {
+        let shipment = &ctx.accounts.shipment;
+        require!(
+            ctx.remaining_accounts.len() == shipment.crate_ids.len(),
+            ErrorCode::ShipmentMembersMismatch
+        );
+        ctx.accounts.config.require_batch_fits(ctx.remaining_accounts.len())?;
+
+        let mut seen: Vec<Pubkey> = Vec::new();
+        let mut summary: Option<ShipmentStatus> = None;
+        for crate_info in ctx.remaining_accounts.iter() {
+            let record: Account<CrateRecord> = Account::try_from(crate_info)?;
+            require!(
+                record.shipment == Some(shipment.key())
+                    && shipment.crate_ids.contains(&record.key())
+                    && !seen.contains(&record.key()),
+                ErrorCode::ShipmentMembersMismatch
+            );
+            seen.push(record.key());
+            summary = Some(match summary {
+                None => ShipmentStatus { status: record.status, uniform: true },
+                Some(summary) => ShipmentStatus {
+                    status: if record.status.progress() < summary.status.progress() {
+                        record.status
+                    } else {
+                        summary.status
+                    },
+                    uniform: summary.uniform && record.status == summary.status,
+                },
+            });
+        }
+        Ok(summary.unwrap_or(ShipmentStatus { status: CrateStatus::Registered, uniform: true }))
+    }
+
+    /// Declares the crate's value, used as the reference for insurance coverage
+    pub fn declare_value(ctx: Context<DeclareValue>, value_cents: u64) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        record.value_cents = value_cents;
+        Ok(())
+    }
+
+    /// Attaches a photo or other media; the authority or a registered lab/inspector may add it
+    pub fn add_media(ctx: Context<AddMedia>, cid: String, mime: String, label: String) -> Result<()> {
+        let uploader = ctx.accounts.uploader.key();
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            uploader == record.authority || ctx.accounts.config.registered_labs.contains(&uploader),
+            ErrorCode::UnauthorizedUpdate
+        );
+        require!(
+            cid.len() <= CrateRecord::MAX_CID_LEN
+                && mime.len() <= MediaRef::MAX_MIME_LEN
+                && label.len() <= MediaRef::MAX_LABEL_LEN,
+            ErrorCode::MediaFieldTooLong
+        );
+        require!(record.media.len() < CrateRecord::MAX_MEDIA, ErrorCode::TooManyMedia);
+        record.media.push(MediaRef { cid, mime, label });
+        Ok(())
+    }
+
+    /// Deletes a crate created in error and reclaims its rent. Only the creator may
+    /// void, only within the grace window and only before anything derives from it;
+    /// afterwards the crate has to be tombstoned instead.
+    pub fn void_crate(ctx: Context<VoidCrate>) -> Result<()> {
+        let record = &ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            record.created_at != 0 && now <= record.created_at + ctx.accounts.config.void_window_secs(),
+            ErrorCode::VoidWindowExpired
+        );
+        // Its children would be left pointing at a closed account
+        require!(!record.consumed, ErrorCode::CrateHasChildren);
+
+        // Only originated crates count toward production; split children were carved from one
+        if record.operation_type == OperationType::Created {
+            let production = &mut ctx.accounts.production;
+            production.produced_grams = production
+                .produced_grams
+                .saturating_sub(record.weight_unit.to_grams(record.weight));
+        }
+        Ok(())
+    }
+
+    /// Marks a crate as a duplicate of `duplicate_of` and freezes it; the record stays on chain
+    pub fn tombstone_crate(ctx: Context<TombstoneCrate>, duplicate_of: Pubkey) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(record.duplicate_of == Pubkey::default(), ErrorCode::AlreadyTombstoned);
+        record.duplicate_of = duplicate_of;
+        record.frozen = true;
+        Ok(())
+    }
+
+    /// Sets the best-before time of the goods, `0` clears it
+    pub fn set_expiry(ctx: Context<SetExpiry>, expiry: i64) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        record.expiry = expiry;
+        Ok(())
+    }
+
+    /// Records a temperature excursion (sensors outside safe bounds) and raises
+    /// `excursion_flag`, which transfers and mixes carry forward to their children
+    pub fn record_excursion(
+        ctx: Context<RecordExcursion>,
+        min_temp: i16,
+        max_temp: i16,
+        duration_seconds: u32,
+        timestamp: i64,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(min_temp <= max_temp, ErrorCode::InvalidExcursion);
+        require!(record.excursions.len() < CrateRecord::MAX_EXCURSIONS, ErrorCode::TooManyExcursions);
+        record.excursions.push(Excursion { min_temp, max_temp, duration_seconds, timestamp });
+        record.excursion_flag = true;
+
+        emit!(ExcursionRecorded {
+            authority: record.authority,
+            crate_record: record.key(),
+            program_event_version: PROGRAM_EVENT_VERSION,
+            min_temp,
+            max_temp,
+            duration_seconds,
+        });
+        Ok(())
+    }
+
+    /// Sets how the goods may be handled; `0` lifts a limit
+    pub fn set_handling_limits(
+        ctx: Context<SetHandlingLimits>,
+        max_tilt_degrees: u8,
+        max_stack_height: u8,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        record.max_tilt_degrees = max_tilt_degrees;
+        record.max_stack_height = max_stack_height;
+        Ok(())
+    }
+
+    /// Logs a handling reading (tilt sensor, stack height). A reading past the crate's
+    /// limits raises `handling_violation`, which transfers and mixes carry forward.
+    pub fn record_handling_event(
+        ctx: Context<RecordHandlingEvent>,
+        tilt_degrees: u8,
+        stack_height: u8,
+        timestamp: i64,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        let tilted = record.max_tilt_degrees != 0 && tilt_degrees > record.max_tilt_degrees;
+        let overstacked = record.max_stack_height != 0 && stack_height > record.max_stack_height;
+        if !(tilted || overstacked) {
+            return Ok(());
+        }
+        record.handling_violation = true;
+
+        emit!(HandlingViolation {
+            authority: record.authority,
+            crate_record: record.key(),
+            program_event_version: PROGRAM_EVENT_VERSION,
+            tilt_degrees,
+            stack_height,
+            timestamp,
+        });
+        Ok(())
+    }
+
+    /// Binds the crate to its EU Digital Product Passport. Splits and transfers inherit
+    /// `dpp_id`; mixes collect every parent passport into `mixed_dpp_ids`.
+    pub fn set_dpp(ctx: Context<SetDpp>, dpp_id: String) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        require!(
+            !dpp_id.is_empty()
+                && dpp_id.len() <= CrateRecord::MAX_DPP_ID_LEN
+                && dpp_id.bytes().all(|b| b.is_ascii_graphic()),
+            ErrorCode::InvalidDppId
+        );
+        record.dpp_id = dpp_id;
+        Ok(())
+    }
+
+    /// Sets the lot/batch code; mixed children inherit it when all parents share one
+    pub fn set_lot_code(ctx: Context<SetLotCode>, lot_code: String) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        require!(lot_code.len() <= CrateRecord::MAX_LOT_CODE_LEN, ErrorCode::LotCodeTooLong);
+        record.lot_code = lot_code;
+        Ok(())
+    }
+
+    /// Tags the crate as organic or conventional; mixes of differing methods are Conventional
+    pub fn set_production_method(
+        ctx: Context<SetProductionMethod>,
+        production_method: ProductionMethod,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        record.production_method = production_method;
+        Ok(())
+    }
+
+    /// Disputes the crate's claimed edge to `parent` without disputing the whole crate.
+    /// Any signer may raise one dispute per edge; lineage-trusting operations reject it.
+    pub fn dispute_edge(ctx: Context<DisputeEdge>, parent: Pubkey, reason_cid: String) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require!(record.parent_crates.contains(&parent), ErrorCode::NotAParentOfChild);
+        require!(
+            !reason_cid.is_empty() && reason_cid.len() <= CrateRecord::MAX_CID_LEN,
+            ErrorCode::InvalidCid
+        );
+        require!(!record.is_edge_disputed(&parent), ErrorCode::EdgeDisputed);
+        require!(record.edge_disputes.len() < CrateRecord::MAX_EDGE_DISPUTES, ErrorCode::TooManyEdgeDisputes);
+        record.edge_disputes.push(EdgeDispute {
+            parent,
+            reason_cid,
+            disputant: ctx.accounts.disputant.key(),
+        });
+        Ok(())
+    }
+
+    /// Clears the dispute on the edge to `parent` (admin only)
+    pub fn resolve_edge_dispute(ctx: Context<ResolveEdgeDispute>, parent: Pubkey) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require!(record.is_edge_disputed(&parent), ErrorCode::EdgeNotDisputed);
+        record.edge_disputes.retain(|dispute| dispute.parent != parent);
+        Ok(())
+    }
+
+    /// Appends a restore point: the CID of a pinned snapshot of the crate's full document set
+    pub fn record_state_checkpoint(ctx: Context<RecordStateCheckpoint>, snapshot_cid: String) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(
+            !snapshot_cid.is_empty()
+                && snapshot_cid.len() <= CrateRecord::MAX_CID_LEN
+                && snapshot_cid.bytes().all(|b| b.is_ascii_alphanumeric()),
+            ErrorCode::InvalidCid
+        );
+        require!(record.checkpoints.len() < CrateRecord::MAX_CHECKPOINTS, ErrorCode::TooManyCheckpoints);
+        record.checkpoints.push(Checkpoint {
+            snapshot_cid,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    /// Sets the operator-facing label; `crate_id` stays the canonical identifier
+    pub fn set_label(ctx: Context<SetLabel>, label: String) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(label.len() <= CrateRecord::MAX_LABEL_LEN, ErrorCode::LabelTooLong);
+        record.label = label;
+        Ok(())
+    }
+
+    /// Stores client-side encrypted notes; `notes_key_cid` points to the wrapped key.
+    /// The program never sees plaintext.
+    pub fn set_encrypted_notes(
+        ctx: Context<SetEncryptedNotes>,
+        encrypted_notes: Vec<u8>,
+        notes_key_cid: String,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(encrypted_notes.len() <= CrateRecord::MAX_NOTES_LEN, ErrorCode::NotesTooLarge);
+        require!(notes_key_cid.len() <= CrateRecord::MAX_CID_LEN, ErrorCode::NotesKeyCidTooLong);
+        record.encrypted_notes = encrypted_notes;
+        record.notes_key_cid = notes_key_cid;
+        Ok(())
+    }
+
+    /// Attaches an insurance policy co-signed by the insurer. Emits `UnderInsured`
+    /// when the combined coverage is below the declared value.
+    pub fn attach_insurance(
+        ctx: Context<AttachInsurance>,
+        policy_id: String,
+        coverage_cents: u64,
+        expiry: i64,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(policy_id.len() <= InsurancePolicy::MAX_POLICY_ID_LEN, ErrorCode::PolicyIdTooLong);
+        require!(
+            record.insurance_policies.len() < CrateRecord::MAX_INSURANCE_POLICIES,
+            ErrorCode::TooManyInsurancePolicies
+        );
+
+        record.insurance_policies.push(InsurancePolicy {
+            insurer: ctx.accounts.insurer.key(),
+            policy_id,
+            coverage_cents,
+            expiry,
+        });
+
+        let total_coverage = record
+            .insurance_policies
+            .iter()
+            .fold(0u64, |total, policy| total.saturating_add(policy.coverage_cents));
+        if total_coverage < record.value_cents {
+            emit!(UnderInsured {
+                authority: record.authority,
+                crate_record: record.key(),
+                program_event_version: PROGRAM_EVENT_VERSION,
+                coverage_cents: total_coverage,
+                value_cents: record.value_cents,
+            });
+        }
+        Ok(())
+    }
+
+    /// Attaches a certification to the crate; the certifier co-signs
+    pub fn add_certification(
+        ctx: Context<AddCertification>,
+        standard: String,
+        expiry: i64,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(standard.len() <= Certification::MAX_STANDARD_LEN, ErrorCode::StandardTooLong);
+        require!(
+            record.certifications.len() < CrateRecord::MAX_CERTIFICATIONS,
+            ErrorCode::TooManyCertifications
+        );
+
+        record.certifications.push(Certification {
+            certifier: ctx.accounts.certifier.key(),
+            standard,
+            expiry,
+            revoked: false,
+        });
+        Ok(())
+    }
+
+    /// Marks certification `index` revoked; only its original certifier may do so.
+    /// The entry is kept for the audit trail.
+    pub fn revoke_certification(ctx: Context<RevokeCertification>, index: u8) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        let cert = record
+            .certifications
+            .get_mut(index as usize)
+            .ok_or(ErrorCode::CertificationNotFound)?;
+        require_keys_eq!(ctx.accounts.certifier.key(), cert.certifier, ErrorCode::UnauthorizedCertifier);
+        cert.revoked = true;
+        Ok(())
+    }
+
+    /// Records the outcome of an inspection by a registered inspector
+    pub fn record_inspection(
+        ctx: Context<RecordInspection>,
+        passed: bool,
+        report_cid: String,
+    ) -> Result<()> {
+        let inspector = ctx.accounts.inspector.key();
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            ctx.accounts.config.registered_labs.contains(&inspector),
+            ErrorCode::UnauthorizedUpdate
+        );
+        require!(report_cid.len() <= CrateRecord::MAX_CID_LEN, ErrorCode::InspectionReportTooLong);
+        require!(
+            record.inspections.len() < CrateRecord::MAX_INSPECTIONS,
+            ErrorCode::TooManyInspections
+        );
+        record.inspections.push(Inspection {
+            inspector,
+            passed,
+            report_cid,
+            timestamp: Clock::get()?.unix_timestamp,
+            revoked: false,
+        });
+        Ok(())
+    }
+
+    /// Marks inspection `index` revoked; only its original inspector may do so.
+    /// The entry is kept for the audit trail.
+    pub fn revoke_inspection(ctx: Context<RevokeInspection>, index: u8) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        let inspection = record
+            .inspections
+            .get_mut(index as usize)
+            .ok_or(ErrorCode::InspectionNotFound)?;
+        require_keys_eq!(ctx.accounts.inspector.key(), inspection.inspector, ErrorCode::UnauthorizedUpdate);
+        inspection.revoked = true;
+        Ok(())
+    }
+
+    /// Records that the crate authority proved possession by signing a receiver-chosen
+    /// nonce (e.g. read from the crate's NFC tag) for `receiver` at `signed_at`. The
+    /// preceding instruction must be an Ed25519 verification of the authority over
+    /// `possession_message`. Each proof must be signed after the last one and within
+    /// `POSSESSION_PROOF_WINDOW_SECS` of the cluster clock, so an old one can't be replayed.
+    pub fn prove_possession(
+        ctx: Context<ProvePossession>,
+        nonce: [u8; 32],
+        signed_at: i64,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require!(nonce != record.last_possession_nonce, ErrorCode::StaleNonce);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            signed_at > record.last_possession_proof
+                && now.abs_diff(signed_at) <= POSSESSION_PROOF_WINDOW_SECS,
+            ErrorCode::StalePossessionProof
+        );
+
+        let receiver = ctx.accounts.receiver.key();
+        let message = possession_message(&record.key(), &receiver, &nonce, signed_at);
+        verify_ed25519_signature(&ctx.accounts.instructions, &record.authority, &message)?;
+
+        record.last_possession_nonce = nonce;
+        record.last_possession_proof = signed_at;
+        Ok(())
+    }
+
+    /// Opens a pending handover to `recipient` that `release_authority` (e.g. a payment
+    /// oracle) must release. The sender keeps ownership until the recipient accepts.
+    /// With `vault_controller` set the recipient must be that program's vault PDA.
+    /// A nonzero `sla_seconds` lets the sender reclaim the crate if it is not accepted in time.
+    pub fn escrow_transfer(
+        ctx: Context<EscrowTransfer>,
+        recipient: Pubkey,
+        release_authority: Pubkey,
+        vault_controller: Option<Pubkey>,
+        sla_seconds: u32,
+    ) -> Result<()> {
+        if let Some(controller) = vault_controller {
+            require_keys_eq!(
+                recipient,
+                vault_address(&ctx.accounts.config, &controller)?,
+                ErrorCode::UnapprovedVaultController
+            );
+        }
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        record.prepare_transfer(&ctx.accounts.config)?;
+        record.escrow = Some(Escrow {
+            recipient,
+            release_authority,
+            released: false,
+            vault_controller: vault_controller.unwrap_or_default(),
+            created_at: Clock::get()?.unix_timestamp,
+            sla_seconds,
+        });
+        Ok(())
+    }
+
+    /// Cancels a pending escrow that was not accepted within its SLA, signed by the sender
+    pub fn reclaim_expired_transfer(ctx: Context<ReclaimExpiredTransfer>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        let escrow = record.escrow.ok_or(ErrorCode::NoPendingEscrow)?;
+        require!(escrow.sla_expired(Clock::get()?.unix_timestamp), ErrorCode::SlaNotExpired);
+        record.escrow = None;
+        Ok(())
+    }
+
+    /// Signed by the escrow's release authority once its condition is met
+    pub fn release_escrow(ctx: Context<ReleaseEscrow>) -> Result<()> {
+        let escrow = ctx
+            .accounts
+            .crate_record
+            .escrow
+            .as_mut()
+            .ok_or(ErrorCode::NoPendingEscrow)?;
+        require_keys_eq!(
+            ctx.accounts.release_authority.key(),
+            escrow.release_authority,
+            ErrorCode::UnauthorizedReleaseAuthority
+        );
+        escrow.released = true;
+        Ok(())
+    }
+
+    /// Completes a released escrow, making the recipient the crate authority
+    pub fn accept_escrow(ctx: Context<AcceptEscrow>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        let escrow = record.escrow.take().ok_or(ErrorCode::NoPendingEscrow)?;
+        require_keys_eq!(ctx.accounts.recipient.key(), escrow.recipient, ErrorCode::UnauthorizedUpdate);
+        require!(escrow.released, ErrorCode::EscrowNotReleased);
+        require!(!escrow.sla_expired(Clock::get()?.unix_timestamp), ErrorCode::SlaExpired);
+        record.hand_over(escrow.recipient, &ctx.accounts.config)?;
+        record.vault_controller = escrow.vault_controller;
+        Ok(())
+    }
+
+    /// Opens a checkpoint (border, port) that every `required` party must sign off
+    /// before the crate can be transferred. An empty list clears it.
+    pub fn open_checkpoint(ctx: Context<OpenCheckpoint>, required: Vec<Pubkey>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        require!(
+            required.len() <= CrateRecord::MAX_CHECKPOINT_PARTIES,
+            ErrorCode::TooManyCheckpointParties
+        );
+        require!(record.checkpoint_complete(), ErrorCode::CheckpointIncomplete);
+        record.checkpoint_required = required;
+        record.checkpoint_approvals = Vec::new();
+        Ok(())
+    }
+
+    /// Records one required party's acknowledgement of the open checkpoint
+    pub fn checkpoint_signoff(ctx: Context<CheckpointSignoff>) -> Result<()> {
+        let party = ctx.accounts.party.key();
+        let record = &mut ctx.accounts.crate_record;
+        require!(record.checkpoint_required.contains(&party), ErrorCode::UnauthorizedUpdate);
+        require!(!record.checkpoint_approvals.contains(&party), ErrorCode::AlreadySignedOff);
+        record.checkpoint_approvals.push(party);
+        Ok(())
+    }
+
+    /// Schedules a handover to `pending_authority` that takes effect at `effective_at`
+    pub fn schedule_transfer(
+        ctx: Context<ScheduleTransfer>,
+        pending_authority: Pubkey,
+        effective_at: i64,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        record.prepare_transfer(&ctx.accounts.config)?;
+        record.scheduled_transfer = Some(ScheduledTransfer { pending_authority, effective_at });
+        Ok(())
+    }
+
+    /// Completes a scheduled transfer once `effective_at` has passed. Permissionless crank.
+    pub fn finalize_scheduled_transfer(ctx: Context<FinalizeScheduledTransfer>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        let scheduled = record.scheduled_transfer.take().ok_or(ErrorCode::NoScheduledTransfer)?;
+        require!(
+            Clock::get()?.unix_timestamp >= scheduled.effective_at,
+            ErrorCode::NotYetEffective
+        );
+        record.hand_over(scheduled.pending_authority, &ctx.accounts.config)
+    }
+
+    /// Binds an SPL mint to the crate so ownership is tracked by token balances. The mint
+    /// must be initialized with the crate authority as its mint authority. Tokenized crates
+    /// can no longer be moved with `transfer_ownership`.
+    pub fn tokenize_crate(ctx: Context<TokenizeCrate>) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        require!(
+            mint_authority(&ctx.accounts.mint.to_account_info())? == Some(authority),
+            ErrorCode::InvalidMint
+        );
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(authority, record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        require!(record.spl_mint.is_none(), ErrorCode::AlreadyTokenized);
+        record.spl_mint = Some(ctx.accounts.mint.key());
+        Ok(())
+    }
+
+    /// Moves a vault-owned crate to `new_authority`. Only the controlling program can
+    /// sign for its vault PDA, so this is reachable solely by CPI from that program.
+    pub fn vault_release(ctx: Context<VaultRelease>, new_authority: Pubkey) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require!(record.vault_controller != Pubkey::default(), ErrorCode::NotInVault);
+        require_keys_eq!(ctx.accounts.vault.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        record.hand_over(new_authority, &ctx.accounts.config)?;
+        record.vault_controller = Pubkey::default();
+        Ok(())
+    }
+
+    /// Lets `delegate` act for the authority on delegable operations until `expires_at`;
+    /// `Pubkey::default()` clears it
+    pub fn set_delegate(ctx: Context<SetDelegate>, delegate: Pubkey, expires_at: i64) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        record.delegate = delegate;
+        record.delegate_expires_at = expires_at;
+        Ok(())
+    }
+
+    /// Splits ownership among co-owners in basis points; shares must total 10000.
+    /// An empty list returns the crate to sole ownership by its authority.
+    pub fn set_owners(ctx: Context<SetOwners>, owners: Vec<OwnerShare>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.authority.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(owners.len() <= CrateRecord::MAX_OWNERS, ErrorCode::TooManyOwners);
+        require!(
+            owners.is_empty() || owners.iter().map(|share| share.bps as u32).sum::<u32>() == BPS_DENOMINATOR,
+            ErrorCode::SharesDontSumTo10000
+        );
+        record.owners = owners;
+        Ok(())
+    }
+
+    /// Moves `bps` of the signer's share to `recipient`; emptied shares are dropped
+    pub fn transfer_shares(ctx: Context<TransferShares>, recipient: Pubkey, bps: u16) -> Result<()> {
+        let sender = ctx.accounts.sender.key();
+        let owners = &mut ctx.accounts.crate_record.owners;
+        let from = owners
+            .iter()
+            .position(|share| share.owner == sender)
+            .ok_or(ErrorCode::InsufficientShares)?;
+        require!(bps > 0 && owners[from].bps >= bps, ErrorCode::InsufficientShares);
+
+        owners[from].bps -= bps;
+        match owners.iter_mut().find(|share| share.owner == recipient) {
+            Some(share) => share.bps += bps,
+            None => owners.push(OwnerShare { owner: recipient, bps }),
+        }
+        owners.retain(|share| share.bps > 0);
+        require!(owners.len() <= CrateRecord::MAX_OWNERS, ErrorCode::TooManyOwners);
+        require!(
+            owners.iter().map(|share| share.bps as u32).sum::<u32>() == BPS_DENOMINATOR,
+            ErrorCode::SharesDontSumTo10000
+        );
+        Ok(())
+    }
+
+    /// Appends an in-transit location ping, dropping the oldest once the trail is full.
+    /// Coordinates are in microdegrees.
+    pub fn log_location(ctx: Context<LogLocation>, lat: i32, lon: i32) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        let now = Clock::get()?.unix_timestamp;
+        record.require_authority_or_delegate(&ctx.accounts.signer.key(), now)?;
+        if record.location_trail.len() == CrateRecord::MAX_LOCATION_TRAIL {
+            record.location_trail.remove(0);
+        }
+        record.location_trail.push(LocationPing {
+            lat,
+            lon,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    /// Records a custody handoff where the sender (the authority) and the receiver each
+    /// report where it happened, in microdegrees. Reports farther apart than
+    /// `handoff_tolerance_meters` set `location_mismatch`.
+    pub fn record_handoff(
+        ctx: Context<RecordHandoff>,
+        sender_lat: i32,
+        sender_lon: i32,
+        receiver_lat: i32,
+        receiver_lon: i32,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require_keys_eq!(ctx.accounts.sender.key(), record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        let in_range = |lat: i32, lon: i32| lat.abs() <= 90_000_000 && lon.abs() <= 180_000_000;
+        require!(
+            in_range(sender_lat, sender_lon) && in_range(receiver_lat, receiver_lon),
+            ErrorCode::InvalidCoordinates
+        );
+
+        let distance_meters = haversine_meters(
+            (sender_lat as i64, sender_lon as i64),
+            (receiver_lat as i64, receiver_lon as i64),
+        );
+        let tolerance = ctx.accounts.config.handoff_tolerance_meters;
+        record.location_mismatch = tolerance != 0 && distance_meters > tolerance as u64;
+        record.last_handoff = Some(Handoff {
+            receiver: ctx.accounts.receiver.key(),
+            sender_lat,
+            sender_lon,
+            receiver_lat,
+            receiver_lon,
+            distance_meters: u32::try_from(distance_meters).unwrap_or(u32::MAX),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    /// Opt-in record that `viewer` inspected this crate; anyone may log themselves
+    pub fn log_access(ctx: Context<LogAccess>) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        require!(
+            record.access_log.len() < CrateRecord::MAX_ACCESS_LOG,
+            ErrorCode::AccessLogFull
+        );
+        record.access_log.push(AccessEntry {
+            viewer: ctx.accounts.viewer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    /// Final receipt signed by the recipient, who must be the current authority.
+    /// With `seal` set the crate is frozen so no further operations can follow.
+    pub fn confirm_delivery(ctx: Context<ConfirmDelivery>, seal: bool) -> Result<()> {
+        let record = &mut ctx.accounts.crate_record;
+        let recipient = ctx.accounts.recipient.key();
+        require_keys_eq!(recipient, record.authority, ErrorCode::UnauthorizedUpdate);
+        require!(!record.frozen, ErrorCode::CrateFrozen);
+        require!(record.status != CrateStatus::Quarantined, ErrorCode::CrateQuarantined);
+
+        let now = Clock::get()?.unix_timestamp;
+        record.set_status(CrateStatus::Delivered, now)?;
+        record.delivered_to = recipient;
+        record.delivered_at = now;
+        if seal {
+            record.frozen = true;
+        }
+
+        emit!(DeliveryConfirmed {
+            authority: record.authority,
+            crate_record: record.key(),
+            program_event_version: PROGRAM_EVENT_VERSION,
+            recipient,
+            delivered_at: now,
+            sealed: seal,
+        });
+        Ok(())
+    }
+}
+
+// ===================
+// CONTEXTS
+// ===================
+
+#[derive(Accounts)]
+#[instruction(crate_id: String)]
+pub struct CreateCrate<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: `[b"hash", sha256(hash)]` PDA, verified and created in `register_hash`.
+    /// Only required when the config enables strict hash uniqueness.
+    #[account(mut)]
+    pub hash_entry: Option<UncheckedAccount<'info>>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + AuthorityProduction::MAX_SIZE,
+        seeds = [b"production", authority.key().as_ref()],
+        bump
+    )]
+    pub production: Account<'info, AuthorityProduction>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + RateLimit::MAX_SIZE,
+        seeds = [b"rate_limit", authority.key().as_ref()],
+        bump
+    )]
+    pub rate_limit: Account<'info, RateLimit>,
+    pub system_program: Program<'info, System>,
+    /// Registered supplier the crate references; omit for none
+    pub supplier: Option<Account<'info, Supplier>>,
+    /// CHECK: `[b"reservation", sha256(crate_id)]` PDA, read in `init_crate` only when the
+    /// program owns it. A live reservation must be held by the creator and is consumed;
+    /// none is required unless the config enables `require_id_reservation`.
+    #[account(mut, seeds = [b"reservation", hashv(&[crate_id.as_bytes()]).as_ref()], bump)]
+    pub reservation: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(crate_id: String)]
+pub struct ReserveCrateId<'info> {
+    #[account(
+        init_if_needed,
+        payer = reserver,
+        space = 8 + IdReservation::MAX_SIZE,
+        // Ids may exceed the 32-byte seed limit, so the seed is their sha256 digest
+        seeds = [b"reservation", hashv(&[crate_id.as_bytes()]).as_ref()],
+        bump
+    )]
+    pub reservation: Account<'info, IdReservation>,
+    #[account(mut)]
+    pub reserver: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(supplier_id: String)]
+pub struct RegisterSupplier<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Supplier::MAX_SIZE,
+        seeds = [b"supplier", supplier_id.as_bytes()],
+        bump
+    )]
+    pub supplier: Account<'info, Supplier>,
+    #[account(seeds = [b"config"], bump, has_one = admin @ ErrorCode::UnauthorizedAdmin)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: String)]
+pub struct CreateTemplate<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + CrateTemplate::MAX_SIZE,
+        seeds = [b"template", authority.key().as_ref(), template_id.as_bytes()],
+        bump
+    )]
+    pub template: Account<'info, CrateTemplate>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddTemplateCertification<'info> {
+    #[account(mut, has_one = authority @ ErrorCode::UnauthorizedUpdate)]
+    pub template: Account<'info, CrateTemplate>,
+    pub authority: Signer<'info>,
+    pub certifier: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateFromTemplate<'info> {
+    pub create: CreateCrate<'info>,
+    pub template: Account<'info, CrateTemplate>,
+}
+
+#[derive(Accounts)]
+#[instruction(crate_id: String)]
+pub struct TransferOwnership<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Box<Account<'info, CrateRecord>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub parent_crate: Box<Account<'info, CrateRecord>>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + RateLimit::MAX_SIZE,
+        seeds = [b"rate_limit", authority.key().as_ref()],
+        bump
+    )]
+    pub rate_limit: Account<'info, RateLimit>,
+    pub system_program: Program<'info, System>,
+    /// Second signer from `high_value_approvers`, required above `high_value_threshold`
+    pub approver: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct RecordReturn<'info> {
+    #[account(init, payer = returner, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Box<Account<'info, CrateRecord>>,
+    #[account(mut)]
+    pub returner: Signer<'info>,
+    #[account(mut)]
+    pub parent_crate: Box<Account<'info, CrateRecord>>,
+    pub upstream_crate: Box<Account<'info, CrateRecord>>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(crate_id: String)]
+pub struct MixCrates<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(crate_id: String)]
+pub struct SplitCrate<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Box<Account<'info, CrateRecord>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub parent_crate: Box<Account<'info, CrateRecord>>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SplitToMany<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub parent_crate: Account<'info, CrateRecord>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(crate_id: String)]
+pub struct SplitOff<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Box<Account<'info, CrateRecord>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub parent_crate: Box<Account<'info, CrateRecord>>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Repackage<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Box<Account<'info, CrateRecord>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub parent_crate: Box<Account<'info, CrateRecord>>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(crate_id: String)]
+pub struct SplitCrateAutoRemainder<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Box<Account<'info, CrateRecord>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub parent_crate: Box<Account<'info, CrateRecord>>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(crate_id: String)]
+pub struct SplitCrateBps<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Box<Account<'info, CrateRecord>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub parent_crate: Box<Account<'info, CrateRecord>>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(crate_id: String)]
+pub struct SplitCrateMixedSpec<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Box<Account<'info, CrateRecord>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub parent_crate: Box<Account<'info, CrateRecord>>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+
+#[derive(Accounts)]
+pub struct UpdateParent<'info> {
+    #[account(mut)]
+    pub parent_crate: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateChild<'info> {
+    #[account(mut)]
+    pub child_crate: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(operation_id: u64)]
+pub struct MarkProcessed<'info> {
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + ProcessedBitmap::MAX_SIZE,
+        seeds = [
+            b"processed",
+            authority.key().as_ref(),
+            &(operation_id / ProcessedBitmap::IDS_PER_PAGE).to_le_bytes()
+        ],
+        bump
+    )]
+    pub processed_bitmap: Account<'info, ProcessedBitmap>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(init, payer = admin, space = 8 + ProgramConfig::MAX_SIZE, seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(mut, seeds = [b"config"], bump, has_one = admin @ ErrorCode::UnauthorizedAdmin)]
+    pub config: Account<'info, ProgramConfig>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Quarantine<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub signer: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateStatus<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecordWeighing<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: address is constrained to the instructions sysvar
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecordReceivingCheck<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub receiver: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CorrectWeight<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub signer: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ProvePossession<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub receiver: Signer<'info>,
+    /// CHECK: address is constrained to the instructions sysvar
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct LogAccess<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub viewer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TakeSample<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub sampler: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct DisputeEdge<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub disputant: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveEdgeDispute<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(seeds = [b"config"], bump, has_one = admin @ ErrorCode::UnauthorizedAdmin)]
+    pub config: Account<'info, ProgramConfig>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ComputeProvenanceRoot<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(shipment_id: String)]
+pub struct CreateShipment<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Shipment::MAX_SIZE,
+        seeds = [b"shipment", authority.key().as_ref(), shipment_id.as_bytes()],
+        bump
+    )]
+    pub shipment: Account<'info, Shipment>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(po_number: String)]
+pub struct CreatePurchaseOrder<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PurchaseOrder::MAX_SIZE,
+        seeds = [b"po", authority.key().as_ref(), po_number.as_bytes()],
+        bump
+    )]
+    pub purchase_order: Account<'info, PurchaseOrder>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FulfillPo<'info> {
+    #[account(mut)]
+    pub purchase_order: Account<'info, PurchaseOrder>,
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    /// Supplier and crate authority
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: String)]
+pub struct CreatePool<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Pool::MAX_SIZE,
+        seeds = [b"pool", authority.key().as_ref(), pool_id.as_bytes()],
+        bump
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositToPool<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFromPool<'info> {
+    #[account(init, payer = authority, space = 8 + CrateRecord::MAX_SIZE)]
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateShipmentMembers<'info> {
+    #[account(mut)]
+    pub shipment: Account<'info, Shipment>,
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TransferShipment<'info> {
+    #[account(mut)]
+    pub shipment: Account<'info, Shipment>,
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyMerkleProof<'info> {
+    pub crate_record: Account<'info, CrateRecord>,
+}
+
+#[derive(Accounts)]
+pub struct AssertNotRecalled<'info> {
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ComputeShipmentStatus<'info> {
+    pub shipment: Account<'info, Shipment>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct DeclareValue<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddMedia<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub uploader: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct VoidCrate<'info> {
+    #[account(mut, close = authority)]
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"production", authority.key().as_ref()],
+        bump
+    )]
+    pub production: Account<'info, AuthorityProduction>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct TombstoneCrate<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecordExcursion<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetHandlingLimits<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecordHandlingEvent<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetDpp<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetLotCode<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetProductionMethod<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetExpiry<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecordCustomsClearance<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub customs_authority: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetOwners<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TransferShares<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub sender: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecordStateCheckpoint<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetLabel<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetEncryptedNotes<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AttachInsurance<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+    pub insurer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddCertification<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+    pub certifier: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeCertification<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub certifier: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecordInspection<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub inspector: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeInspection<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub inspector: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FreezeByAuthority<'info> {
+    #[account(seeds = [b"config"], bump, has_one = admin @ ErrorCode::UnauthorizedAdmin)]
+    pub config: Account<'info, ProgramConfig>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(authority: Pubkey)]
+pub struct SetProductionQuota<'info> {
+    #[account(seeds = [b"config"], bump, has_one = admin @ ErrorCode::UnauthorizedAdmin)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + AuthorityProduction::MAX_SIZE,
+        seeds = [b"production", authority.as_ref()],
+        bump
+    )]
+    pub production: Account<'info, AuthorityProduction>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EscrowTransfer<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct OpenCheckpoint<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CheckpointSignoff<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub party: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RefreshCarbonIntensity<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+}
+
+#[derive(Accounts)]
+pub struct ScheduleTransfer<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeScheduledTransfer<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct TokenizeCrate<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+    /// CHECK: SPL mint, owner and layout verified by `mint_authority`
+    pub mint: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct VaultRelease<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    /// The vault PDA, signed for by its controller via `invoke_signed`
+    pub vault: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseEscrow<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub release_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimExpiredTransfer<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptEscrow<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub recipient: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetDelegate<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct LogLocation<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    /// Authority or delegate
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecordHandoff<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub sender: Signer<'info>,
+    pub receiver: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ConfirmDelivery<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    pub recipient: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct LinkMixChildren<'info> {
+    pub child_crate: Account<'info, CrateRecord>,
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct DedupeLineage<'info> {
+    #[account(seeds = [b"config"], bump, has_one = admin @ ErrorCode::UnauthorizedAdmin)]
+    pub config: Account<'info, ProgramConfig>,
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+}
+
+#[derive(Accounts)]
+pub struct BulkRecall<'info> {
+    #[account(seeds = [b"config"], bump, has_one = admin @ ErrorCode::UnauthorizedAdmin)]
+    pub config: Account<'info, ProgramConfig>,
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub root_crate: Account<'info, CrateRecord>,
+}
+
+#[derive(Accounts)]
+pub struct RefreshParentSum<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+}
+
+#[derive(Accounts)]
+pub struct CheckExpiry<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CheckStaleness<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AuditBatchConservation<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ComputeIndexDigest<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ComputeOwnerInventory<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyTamper<'info> {
+    #[account(mut)]
+    pub crate_record: Account<'info, CrateRecord>,
+}
+
+#[derive(Accounts)]
+pub struct ReadCrateRecord<'info> {
+    pub crate_record: Account<'info, CrateRecord>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyLineageInvariants<'info> {
+    pub crate_record: Account<'info, CrateRecord>,
+}
+
+// ===================
+// DATA STRUCTURE
+// ===================
+
+#[account]
+
+pub struct CrateRecord {
+    pub authority: Pubkey,
+    pub crate_id: String,
+    pub crate_did: String,  // DID for crate
+    pub owner_did: String,  // DID for owner
+    pub device_did: String, // DID for NFC or scanner device
+    pub location: String,   // Location string (lat,long)
+    pub weight: u32,
+    pub timestamp: i64,
+    pub hash: String,
+    pub ipfs_cid: String,
+
+    pub parent_crates: Vec<Pubkey>,
+    pub child_crates: Vec<Pubkey>,
+    pub parent_weights: Vec<u32>,
+    pub split_distribution: Vec<u32>,
+    pub operation_type: OperationType,
+    pub frozen: bool,
+    pub status: CrateStatus,
+    pub status_history: Vec<StatusEntry>,
+    pub weighed_by: Pubkey,
+    pub weight_verified: bool,
+    pub access_log: Vec<AccessEntry>,
+    pub value_cents: u64,
+    pub insurance_policies: Vec<InsurancePolicy>,
+    pub samples: Vec<SampleEntry>,
+    pub lineage_hash: [u8; 32], // sha256(hash || parent lineage hashes)
+    pub provenance_root: [u8; 32],
+    pub shipment: Option<Pubkey>,
+    pub weight_unit: WeightUnit,
+    pub last_possession_nonce: [u8; 32],
+    pub last_possession_proof: i64,
+    pub carbon_grams: u64, // cumulative footprint, inherited from parents
+    pub delivered_to: Pubkey,
+    pub delivered_at: i64,
+    pub weight_delta: i32, // weight change on the transfer that created this crate
+    pub cumulative_weight_delta: i32, // sum of weight_delta down the transfer chain
+    pub certifications: Vec<Certification>, // carried over on transfer
+    pub encrypted_notes: Vec<u8>, // opaque, encrypted client-side
+    pub notes_key_cid: String,
+    pub product_type: String,
+    pub delegate: Pubkey, // default = no delegate
+    pub location_trail: Vec<LocationPing>, // oldest first, capped ring buffer
+    pub escrow: Option<Escrow>,
+    pub mix_weight_delta: i64, // declared output minus input grams of a mix_and_split
+    pub label: String, // e.g. "Pallet 17, Bay C"
+    pub tampered: bool, // set by verify_tamper, never cleared
+    pub delegate_expires_at: i64,
+    pub po_number: String, // purchase order this crate fulfils, empty if none
+    pub media: Vec<MediaRef>,
+    pub created_at: i64, // cluster time at creation or split, starts the void grace window
+    pub duplicate_of: Pubkey, // set by tombstone_crate, default = live
+    pub parent_weight_at_split: u32, // parent weight before the split took effect, never rewritten
+    pub recalled: bool, // set by bulk_recall, never cleared
+    pub expiry: i64, // unix seconds, 0 = none; a mix takes the earliest parent expiry
+    pub customs_clearances: Vec<CustomsClearance>,
+    pub receiving_check: Option<ReceivingCheck>, // latest check only
+    pub receiving_discrepancy: bool,
+    pub owners: Vec<OwnerShare>, // co-owners in bps, empty = sole ownership by authority
+    pub cached_parent_weight_sum: u32, // grams, set on mix and by refresh_parent_sum
+    pub vault_controller: Pubkey, // program whose vault PDA is the authority, default = none
+    pub stale: bool, // set by check_staleness, cleared once the crate is fresh again
+    pub return_reason_cid: String, // set on `Returned` records
+    pub checkpoints: Vec<Checkpoint>, // oldest first
+    pub min_hold_seconds: u32, // transfers blocked until `timestamp` + this; splits keep the deadline
+    pub weight_corrections: Vec<WeightCorrection>,
+    pub lot_code: String,
+    pub excursions: Vec<Excursion>,
+    pub excursion_flag: bool, // a temperature excursion was recorded here or upstream
+    pub dpp_id: String,             // Digital Product Passport, see `set_dpp`
+    pub mixed_dpp_ids: Vec<String>, // distinct passports of mixed-in ancestors
+    pub burned: bool, // expired under `auto_burn_on_expiry`, never cleared
+    pub supplier: Pubkey, // `Supplier` PDA, default = none
+    pub display_decimals: u8, // rendering hint for clients only, never used in weight math
+    pub spl_mint: Option<Pubkey>, // set by tokenize_crate
+    pub blended_grade: u16, // weight-averaged parent grade from mix_crates, 0 = none
+    pub edge_disputes: Vec<EdgeDispute>, // open disputes on single parent edges
+    pub scheduled_transfer: Option<ScheduledTransfer>,
+    pub total_distance_meters: u64, // great-circle distance summed across transfers with coordinates
+    pub transfer_count: u32, // ownership changes in this crate's lineage, in place or by new record
+    pub pool: Option<Pubkey>, // pool deposited into or withdrawn from
+    pub checkpoint_required: Vec<Pubkey>, // parties who must sign off before a transfer
+    pub checkpoint_approvals: Vec<Pubkey>,
+    pub last_handoff: Option<Handoff>,
+    pub location_mismatch: bool, // the last handoff's two reports disagreed
+    pub carbon_intensity_mg_per_g: u64, // as of the last `refresh_carbon_intensity`
+    pub production_method: ProductionMethod,
+    pub inspections: Vec<Inspection>, // kept for this custody only, not carried over
+    pub max_tilt_degrees: u8, // handling limits, 0 = no limit
+    pub max_stack_height: u8,
+    pub handling_violation: bool, // a handling limit was breached here or upstream
+    pub consumed: bool, // goods moved on into child crates; split children keep siblings in `child_crates`
+}
+
+impl CrateRecord {
+
+    pub const MAX_PARENTS: usize = 10;
+    pub const MAX_CHILDREN: usize = 10;
+    pub const MAX_SPLIT_FANOUT: usize = 4; // children created by one `split_to_many`
+    pub const MAX_STATUS_HISTORY: usize = 8;
+    pub const MAX_ACCESS_LOG: usize = 8;
+    pub const MAX_INSURANCE_POLICIES: usize = 3;
+    pub const MAX_SAMPLES: usize = 5;
+    pub const MAX_CERTIFICATIONS: usize = 3;
+    pub const MAX_NOTES_LEN: usize = 512;
+    pub const MAX_CID_LEN: usize = 64;
+    pub const MAX_PRODUCT_TYPE_LEN: usize = 32;
+    pub const MAX_LOCATION_TRAIL: usize = 8;
+    pub const MAX_LABEL_LEN: usize = 48;
+    pub const MAX_MEDIA: usize = 4;
+    pub const MAX_CUSTOMS_CLEARANCES: usize = 4;
+    pub const MAX_OWNERS: usize = 8;
+    pub const MAX_CHECKPOINTS: usize = 4;
+    pub const MAX_WEIGHT_CORRECTIONS: usize = 3;
+    pub const MAX_LOT_CODE_LEN: usize = 32;
+    pub const MAX_EXCURSIONS: usize = 4;
+    pub const MAX_DPP_ID_LEN: usize = 64;
+    pub const MAX_MIXED_DPP_IDS: usize = 4;
+    pub const MAX_DISPLAY_DECIMALS: u8 = 6;
+    pub const MAX_EDGE_DISPUTES: usize = 4;
+    pub const MAX_CHECKPOINT_PARTIES: usize = 4;
+    pub const MAX_INSPECTIONS: usize = 4;
+
+    /// `get_flags` bit positions. Voided crates have no flag: their account is closed.
+    pub const FLAG_FROZEN: u32 = 1 << 0;
+    pub const FLAG_SEALED: u32 = 1 << 1; // delivered and frozen
+    pub const FLAG_CONSUMED: u32 = 1 << 2; // see `consumed`
+    pub const FLAG_TOMBSTONED: u32 = 1 << 3;
+    pub const FLAG_RECALLED: u32 = 1 << 4;
+    pub const FLAG_EXPIRED: u32 = 1 << 5;
+    pub const FLAG_TAMPERED: u32 = 1 << 6;
+    pub const FLAG_WEIGHT_VERIFIED: u32 = 1 << 7;
+    pub const FLAG_ESCROW_PENDING: u32 = 1 << 8;
+    pub const FLAG_RECEIVING_DISCREPANCY: u32 = 1 << 9;
+    pub const FLAG_STALE: u32 = 1 << 10;
+    pub const FLAG_EXCURSION: u32 = 1 << 11;
+    pub const FLAG_BURNED: u32 = 1 << 12;
+    pub const FLAG_EDGE_DISPUTED: u32 = 1 << 13; // any parent edge
+    pub const FLAG_HANDLING_VIOLATION: u32 = 1 << 14;
+    pub const MAX_SIZE: usize =
+        32 + // authority
+        4 + 64 + // crate_id
+        4 + 64 + // crate_did
+        4 + 64 + // owner_did
+        4 + 64 + // device_did
+        4 + 64 + // location
+        4 + 8 +  // weight + timestamp
+        4 + 64 + // hash
+        4 + 64 + // ipfs_cid
+        4 + (Self::MAX_PARENTS * 32) +
+        4 + (Self::MAX_CHILDREN * 32) +
+        4 + (Self::MAX_PARENTS * 4) +
+        4 + (Self::MAX_CHILDREN * 4) +
+        1 + // operation_type
+        1 + // frozen
+        1 + // status
+        4 + (Self::MAX_STATUS_HISTORY * StatusEntry::SIZE) +
+        32 + // weighed_by
+        1 +  // weight_verified
+        4 + (Self::MAX_ACCESS_LOG * AccessEntry::SIZE) +
+        8 +  // value_cents
+        4 + (Self::MAX_INSURANCE_POLICIES * InsurancePolicy::MAX_SIZE) +
+        4 + (Self::MAX_SAMPLES * SampleEntry::SIZE) +
+        32 + // lineage_hash
+        32 + // provenance_root
+        1 + 32 + // shipment
+        1 +  // weight_unit
+        32 + // last_possession_nonce
+        8 +  // last_possession_proof
+        8 +  // carbon_grams
+        32 + // delivered_to
+        8 +  // delivered_at
+        4 +  // weight_delta
+        4 +  // cumulative_weight_delta
+        4 + (Self::MAX_CERTIFICATIONS * Certification::MAX_SIZE) +
+        4 + Self::MAX_NOTES_LEN + // encrypted_notes
+        4 + Self::MAX_CID_LEN +   // notes_key_cid
+        4 + Self::MAX_PRODUCT_TYPE_LEN +
+        32 + // delegate
+        4 + (Self::MAX_LOCATION_TRAIL * LocationPing::SIZE) +
+        1 + Escrow::SIZE +
+        8 +  // mix_weight_delta
+        4 + Self::MAX_LABEL_LEN +
+        1 +  // tampered
+        8 +  // delegate_expires_at
+        4 + 32 + // po_number
+        4 + (Self::MAX_MEDIA * MediaRef::MAX_SIZE) +
+        8 +  // created_at
+        32 + // duplicate_of
+        4 +  // parent_weight_at_split
+        1 +  // recalled
+        8 +  // expiry
+        4 + (Self::MAX_CUSTOMS_CLEARANCES * CustomsClearance::MAX_SIZE) +
+        1 + ReceivingCheck::SIZE +
+        1 +  // receiving_discrepancy
+        4 + (Self::MAX_OWNERS * OwnerShare::SIZE) +
+        4 +  // cached_parent_weight_sum
+        32 + // vault_controller
+        1 +  // stale
+        4 + Self::MAX_CID_LEN + // return_reason_cid
+        4 + (Self::MAX_CHECKPOINTS * Checkpoint::MAX_SIZE) +
+        4 +  // min_hold_seconds
+        4 + (Self::MAX_WEIGHT_CORRECTIONS * WeightCorrection::MAX_SIZE) +
+        4 + Self::MAX_LOT_CODE_LEN +
+        4 + (Self::MAX_EXCURSIONS * Excursion::SIZE) +
+        1 +  // excursion_flag
+        4 + Self::MAX_DPP_ID_LEN +
+        4 + (Self::MAX_MIXED_DPP_IDS * (4 + Self::MAX_DPP_ID_LEN)) +
+        1 +  // burned
+        32 + // supplier
+        1 +  // display_decimals
+        1 + 32 + // spl_mint
+        2 +  // blended_grade
+        4 + (Self::MAX_EDGE_DISPUTES * EdgeDispute::MAX_SIZE) +
+        1 + ScheduledTransfer::SIZE +
+        8 + // total_distance_meters
+        4 + // transfer_count
+        1 + 32 + // pool
+        4 + (Self::MAX_CHECKPOINT_PARTIES * 32) +
+        4 + (Self::MAX_CHECKPOINT_PARTIES * 32) +
+        1 + Handoff::SIZE +
+        1 + // location_mismatch
+        8 + // carbon_intensity_mg_per_g
+        1 + // production_method
+        4 + (Self::MAX_INSPECTIONS * Inspection::MAX_SIZE) + // inspections
+        1 +  // max_tilt_degrees
+        1 +  // max_stack_height
+        1 +  // handling_violation
+        1;   // consumed
+
+    /// True when every required checkpoint party has signed off, or none is required
+    pub fn checkpoint_complete(&self) -> bool {
+        self.checkpoint_required
+            .iter()
+            .all(|party| self.checkpoint_approvals.contains(party))
+    }
+
+    /// Checks shared by every path that reads the crate as the parent of a new record
+    pub fn prepare_derivation(&self, config: &ProgramConfig) -> Result<()> {
+        // The goods already moved on into child crates
+        require!(!self.consumed, ErrorCode::CrateConsumed);
+        self.prepare_split_derivation(config)
+    }
+
+    /// `prepare_derivation` for a split created one child per call: the crate may already
+    /// be consumed, but only into that same split, which `consume_into_split` checks
+    pub fn prepare_split_derivation(&self, config: &ProgramConfig) -> Result<()> {
+        require!(!self.frozen, ErrorCode::CrateFrozen);
+        // A child record would carry the goods out of quarantine
+        require!(self.status != CrateStatus::Quarantined, ErrorCode::CrateQuarantined);
+        // Ownership of a tokenized crate moves with the token, and a child record has none
+        require!(self.spl_mint.is_none(), ErrorCode::CrateTokenized);
+        // Burning waits for someone to call `check_expiry`; the goods must not move on meanwhile
+        if config.auto_burn_on_expiry {
+            require!(!self.is_expired(Clock::get()?.unix_timestamp), ErrorCode::CrateExpired);
+        }
+        Ok(())
+    }
+
+    /// Whether the crate has an expiry and it has passed at `now`
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expiry != 0 && now >= self.expiry
+    }
+
+    /// Checks shared by every path that hands the crate to a new authority. A pending
+    /// escrow or scheduled transfer must complete (and be cleared) or be cancelled first.
+    pub fn prepare_transfer(&self, config: &ProgramConfig) -> Result<()> {
+        self.prepare_derivation(config)?;
+        require!(self.escrow.is_none(), ErrorCode::EscrowPending);
+        require!(self.scheduled_transfer.is_none(), ErrorCode::TransferAlreadyScheduled);
+        require!(self.checkpoint_complete(), ErrorCode::CheckpointIncomplete);
+        require!(
+            config.max_transfers == 0 || self.transfer_count < config.max_transfers,
+            ErrorCode::MaxTransfersReached
+        );
+        Ok(())
+    }
+
+    /// Marks the goods as moved on into `child`, a new record holding `weight` of them
+    pub fn consume_into(&mut self, child: Pubkey, weight: u32) -> Result<()> {
+        require!(self.child_crates.len() < Self::MAX_CHILDREN, ErrorCode::TooManyChildren);
+        self.push_child(child, weight);
+        self.consumed = true;
+        Ok(())
+    }
+
+    /// Marks the goods as moved on into the split `child_keys`/`child_weights` when its
+    /// first child is created; every later child must name the same split
+    pub fn consume_into_split(&mut self, child_keys: &[Pubkey], child_weights: &[u32]) -> Result<()> {
+        if self.consumed {
+            require!(
+                self.child_crates == child_keys && self.split_distribution == child_weights,
+                ErrorCode::CrateConsumed
+            );
+        } else {
+            self.child_crates = child_keys.to_vec();
+            self.split_distribution = child_weights.to_vec();
+            self.consumed = true;
+        }
+        Ok(())
+    }
+
+    /// Moves the crate to `new_authority` in place, counting it as a transfer
+    pub fn hand_over(&mut self, new_authority: Pubkey, config: &ProgramConfig) -> Result<()> {
+        self.prepare_transfer(config)?;
+        self.authority = new_authority;
+        self.transfer_count = self.transfer_count.saturating_add(1);
+        Ok(())
+    }
+
+    /// Who will own the crate once its pending scheduled transfer or escrow completes
+    pub fn eventual_authority(&self) -> Pubkey {
+        match (&self.scheduled_transfer, &self.escrow) {
+            (Some(scheduled), _) => scheduled.pending_authority,
+            (None, Some(escrow)) => escrow.recipient,
+            (None, None) => self.authority,
+        }
+    }
+
+    /// Checks `key` may act on delegable operations; an expired delegate counts as absent
+    pub fn require_authority_or_delegate(&self, key: &Pubkey, now: i64) -> Result<()> {
+        if *key == self.authority || self.is_owner(key) {
+            return Ok(());
+        }
+        require!(
+            self.delegate != Pubkey::default() && *key == self.delegate,
+            ErrorCode::UnauthorizedUpdate
+        );
+        require!(now < self.delegate_expires_at, ErrorCode::DelegateExpired);
+        Ok(())
+    }
+
+    /// Whether the edge to `parent` has an open dispute
+    pub fn is_edge_disputed(&self, parent: &Pubkey) -> bool {
+        self.edge_disputes.iter().any(|dispute| dispute.parent == *parent)
+    }
+
+    /// Whether `key` holds a nonzero ownership share
+    pub fn is_owner(&self, key: &Pubkey) -> bool {
+        self.owners.iter().any(|share| share.owner == *key && share.bps > 0)
+    }
+
+    /// Packs the crate's flags as of `now` into `FLAG_*` bits
+    pub fn flags(&self, now: i64) -> u32 {
+        [
+            (Self::FLAG_FROZEN, self.frozen),
+            (Self::FLAG_SEALED, self.frozen && self.status == CrateStatus::Delivered),
+            (Self::FLAG_CONSUMED, self.consumed),
+            (Self::FLAG_TOMBSTONED, self.duplicate_of != Pubkey::default()),
+            (Self::FLAG_RECALLED, self.recalled),
+            (Self::FLAG_EXPIRED, self.expiry != 0 && now >= self.expiry),
+            (Self::FLAG_TAMPERED, self.tampered),
+            (Self::FLAG_WEIGHT_VERIFIED, self.weight_verified),
+            (Self::FLAG_ESCROW_PENDING, self.escrow.is_some()),
+            (Self::FLAG_RECEIVING_DISCREPANCY, self.receiving_discrepancy),
+            (Self::FLAG_STALE, self.stale),
+            (Self::FLAG_EXCURSION, self.excursion_flag),
+            (Self::FLAG_BURNED, self.burned),
+            (Self::FLAG_EDGE_DISPUTED, !self.edge_disputes.is_empty()),
+            (Self::FLAG_HANDLING_VIOLATION, self.handling_violation),
+        ]
+        .iter()
+        .filter(|(_, set)| *set)
+        .fold(0, |flags, (bit, _)| flags | bit)
+    }
+
+    /// Whether any unrevoked inspection passed
+    pub fn has_passing_inspection(&self) -> bool {
+        self.inspections.iter().any(|inspection| inspection.passed && !inspection.revoked)
+    }
+
+    /// Whether any unrevoked certification is still in force at `now`
+    pub fn has_valid_certification(&self, now: i64) -> bool {
+        self.certifications.iter().any(|cert| !cert.revoked && cert.expiry > now)
+    }
+
+    /// Appends a child, extending `split_distribution` with its weight when it is kept
+    pub fn push_child(&mut self, child: Pubkey, weight: u32) {
+        self.child_crates.push(child);
+        if !self.split_distribution.is_empty() {
+            self.split_distribution.push(weight);
+        }
+    }
+
+    /// Changes the weight of the crate at `key` in place. The scale signed the old weight,
+    /// and a split child's own entry in `split_distribution` follows the new one.
+    pub fn set_weight(&mut self, key: Pubkey, weight: u32) -> Result<()> {
+        require!(self.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+        if let Some(index) = self.child_crates.iter().position(|child| *child == key) {
+            if let Some(entry) = self.split_distribution.get_mut(index) {
+                *entry = weight;
+            }
+        }
+        self.weight = weight;
+        self.weight_verified = false;
+        Ok(())
+    }
+
+    /// `min_hold_seconds` for a child record at `timestamp` that keeps this crate's deadline
+    pub fn hold_remaining_at(&self, timestamp: i64) -> u32 {
+        if self.min_hold_seconds == 0 {
+            return 0;
+        }
+        let deadline = self.timestamp.saturating_add(self.min_hold_seconds as i64);
+        deadline.saturating_sub(timestamp).clamp(0, u32::MAX as i64) as u32
+    }
+
+    /// Every parent has a weight, and a split distribution (if any) covers every child
+    pub fn lineage_invariants_hold(&self) -> bool {
+        self.parent_weights.len() == self.parent_crates.len()
+            && (self.split_distribution.is_empty()
+                || self.split_distribution.len() == self.child_crates.len())
+    }
+
+    /// Moves to `status` if the transition is allowed and appends it to the history
+    pub fn set_status(&mut self, status: CrateStatus, timestamp: i64) -> Result<()> {
+        require!(self.status.can_transition_to(status), ErrorCode::InvalidStatusTransition);
+        require!(
+            self.status_history.len() < Self::MAX_STATUS_HISTORY,
+            ErrorCode::StatusHistoryFull
+        );
+        self.status = status;
+        self.status_history.push(StatusEntry { status, timestamp });
+        Ok(())
+    }
+
+    /// `set_status` for changes that must always land: a full history drops its oldest entry
+    pub fn force_status(&mut self, status: CrateStatus, timestamp: i64) -> Result<()> {
+        if self.status_history.len() >= Self::MAX_STATUS_HISTORY {
+            self.status_history.remove(0);
+        }
+        self.set_status(status, timestamp)
+    }
+
+    /// Portion of this crate's carbon footprint carried by `weight` of it
+    pub fn carbon_share(&self, weight: u32) -> u64 {
+        if self.weight == 0 {
+            return 0;
+        }
+        (self.carbon_grams as u128 * weight as u128 / self.weight as u128) as u64
+    }
+
+    /// Milligrams of CO2e per gram of goods; 0 for an empty crate
+    pub fn carbon_intensity(&self) -> u64 {
+        let grams = self.weight_unit.to_grams(self.weight);
+        if grams == 0 {
+            return 0;
+        }
+        u64::try_from(self.carbon_grams as u128 * 1000 / grams as u128).unwrap_or(u64::MAX)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OperationType {
+    Created,
+    Transferred,
+    Mixed,
+    Split,
+    MixedAndSplit,
+    Returned,
+    Repackaged,
+    PoolWithdrawal,
+}
+
+/// Default status for records created by `operation`, see `ProgramConfig::operation_statuses`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct OperationStatus {
+    pub operation: OperationType,
+    pub status: CrateStatus,
+}
+
+impl OperationStatus {
+    pub const SIZE: usize = 1 + 1;
+}
+
+/// How the goods were produced; a crate is exactly one of these
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ProductionMethod {
+    Unspecified,
+    Conventional,
+    Organic,
+}
+
+/// Unit `weight` is expressed in; mixes normalize to grams
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum WeightUnit {
+    Grams,
+    Kilograms,
+    Tonnes,
+}
+
+impl WeightUnit {
+    pub fn to_grams(self, weight: u32) -> u64 {
+        let grams_per_unit: u64 = match self {
+            WeightUnit::Grams => 1,
+            WeightUnit::Kilograms => 1_000,
+            WeightUnit::Tonnes => 1_000_000,
+        };
+        weight as u64 * grams_per_unit
+    }
+}
+
+/// Rolled-up member status returned by `compute_shipment_status`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ShipmentStatus {
+    pub status: CrateStatus, // least advanced member; Registered for an empty shipment
+    pub uniform: bool,       // every member has `status`
+}
+
+/// Operational status of a crate, independent of how it was created
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum CrateStatus {
+    Registered,
+    InTransit,
+    Received,
+    Processed,
+    Delivered,
+    Quarantined, // held pending inspection, see `quarantine_crate`
+}
+
+impl CrateStatus {
+    /// Delivered is terminal, crates never return to Registered, and
+    /// a status change must actually change the status
+    pub fn can_transition_to(self, next: CrateStatus) -> bool {
+        self != CrateStatus::Delivered && next != CrateStatus::Registered && next != self
+    }
+
+    /// How far along the crate is, for "least advanced" comparisons. Quarantined ranks
+    /// below everything since the crate cannot move on until it is released.
+    pub fn progress(self) -> u8 {
+        match self {
+            CrateStatus::Quarantined => 0,
+            status => status as u8 + 1,
+        }
+    }
+}
+
+/// Fractional ownership of a crate
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct OwnerShare {
+    pub owner: Pubkey,
+    pub bps: u16,
+}
+
+impl OwnerShare {
+    pub const SIZE: usize = 32 + 2;
+}
+
+/// Receiver-side weighing, see `record_receiving_check`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct ReceivingCheck {
+    pub receiver: Pubkey,
+    pub measured_weight: u32,
+    pub delta: i64, // measured minus recorded weight
+    pub timestamp: i64,
+}
+
+impl ReceivingCheck {
+    pub const SIZE: usize = 32 + 4 + 8 + 8;
+}
+
+/// Customs sign-off for one border crossing
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct CustomsClearance {
+    pub port: String,
+    pub cleared_by: Pubkey,
+    pub clearance_doc_cid: String,
+    pub timestamp: i64,
+}
+
+impl CustomsClearance {
+    pub const MAX_PORT_LEN: usize = 32;
+    pub const MAX_SIZE: usize = 4 + Self::MAX_PORT_LEN + 32 + 4 + CrateRecord::MAX_CID_LEN + 8;
+}
+
+/// Time-locked handover, see `schedule_transfer`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct ScheduledTransfer {
+    pub pending_authority: Pubkey,
+    pub effective_at: i64,
+}
+
+impl ScheduledTransfer {
+    pub const SIZE: usize = 32 + 8;
+}
+
+/// Open dispute on one parent edge, see `dispute_edge`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct EdgeDispute {
+    pub parent: Pubkey,
+    pub reason_cid: String,
+    pub disputant: Pubkey,
+}
+
+impl EdgeDispute {
+    pub const MAX_SIZE: usize = 32 + 4 + CrateRecord::MAX_CID_LEN + 32;
+}
+
+/// Audit entry for `correct_weight`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct WeightCorrection {
+    pub prior_weight: u32,
+    pub delta: i64,
+    pub reason_cid: String,
+    pub corrected_by: Pubkey,
+    pub timestamp: i64,
+}
+
+impl WeightCorrection {
+    pub const MAX_SIZE: usize = 4 + 8 + 4 + CrateRecord::MAX_CID_LEN + 32 + 8;
+}
+
+/// Temperature excursion, in tenths of a degree Celsius
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Excursion {
+    pub min_temp: i16,
+    pub max_temp: i16,
+    pub duration_seconds: u32,
+    pub timestamp: i64,
+}
+
+impl Excursion {
+    pub const SIZE: usize = 2 + 2 + 4 + 8;
+}
+
+/// Pinned full-state snapshot, see `record_state_checkpoint`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Checkpoint {
+    pub snapshot_cid: String,
+    pub timestamp: i64,
+}
+
+impl Checkpoint {
+    pub const MAX_SIZE: usize = 4 + CrateRecord::MAX_CID_LEN + 8;
+}
+
+/// Photo or document attached to a crate, rendered inline by tracking UIs
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct MediaRef {
+    pub cid: String,
+    pub mime: String, // e.g. "image/jpeg"
+    pub label: String,
+}
+
+impl MediaRef {
+    pub const MAX_MIME_LEN: usize = 32;
+    pub const MAX_LABEL_LEN: usize = 32;
+    pub const MAX_SIZE: usize =
+        4 + CrateRecord::MAX_CID_LEN +
+        4 + Self::MAX_MIME_LEN +
+        4 + Self::MAX_LABEL_LEN;
+}
+
+/// Pending conditional handover, see `escrow_transfer`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Escrow {
+    pub recipient: Pubkey,
+    pub release_authority: Pubkey,
+    pub released: bool,
+    pub vault_controller: Pubkey, // default unless the recipient is a vault PDA
+    pub created_at: i64,
+    pub sla_seconds: u32, // acceptance deadline after `created_at`, 0 = none
+}
+
+impl Escrow {
+    pub const SIZE: usize = 32 + 32 + 1 + 32 + 8 + 4;
+
+    pub fn sla_expired(&self, now: i64) -> bool {
+        self.sla_seconds != 0 && now >= self.created_at.saturating_add(self.sla_seconds as i64)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct LocationPing {
+    pub lat: i32, // microdegrees
+    pub lon: i32,
+    pub timestamp: i64,
+}
+
+impl LocationPing {
+    pub const SIZE: usize = 4 + 4 + 8;
+}
+
+/// Custody handoff with both parties' reported position, see `record_handoff`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Handoff {
+    pub receiver: Pubkey,
+    pub sender_lat: i32, // microdegrees
+    pub sender_lon: i32,
+    pub receiver_lat: i32,
+    pub receiver_lon: i32,
+    pub distance_meters: u32, // between the two reports
+    pub timestamp: i64,
+}
+
+impl Handoff {
+    pub const SIZE: usize = 32 + 4 + 4 + 4 + 4 + 4 + 8;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct StatusEntry {
+    pub status: CrateStatus,
+    pub timestamp: i64,
+}
+
+impl StatusEntry {
+    pub const SIZE: usize = 1 + 8;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct AccessEntry {
+    pub viewer: Pubkey,
+    pub timestamp: i64,
+}
+
+impl AccessEntry {
+    pub const SIZE: usize = 32 + 8;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct InsurancePolicy {
+    pub insurer: Pubkey,
+    pub policy_id: String,
+    pub coverage_cents: u64,
+    pub expiry: i64,
+}
+
+impl InsurancePolicy {
+    pub const MAX_POLICY_ID_LEN: usize = 32;
+    pub const MAX_SIZE: usize = 32 + 4 + Self::MAX_POLICY_ID_LEN + 8 + 8;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Certification {
+    pub certifier: Pubkey,
+    pub standard: String, // e.g. "MSC", "ASC"
+    pub expiry: i64,
+    pub revoked: bool,
+}
+
+impl Certification {
+    pub const MAX_STANDARD_LEN: usize = 32;
+    pub const MAX_SIZE: usize = 32 + 4 + Self::MAX_STANDARD_LEN + 8 + 1;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Inspection {
+    pub inspector: Pubkey,
+    pub passed: bool,
+    pub report_cid: String,
+    pub timestamp: i64,
+    pub revoked: bool,
+}
+
+impl Inspection {
+    pub const MAX_SIZE: usize = 32 + 1 + 4 + CrateRecord::MAX_CID_LEN + 8 + 1;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct SampleEntry {
+    pub sampler: Pubkey,
+    pub sample_weight: u32,
+    pub timestamp: i64,
+}
+
+impl SampleEntry {
+    pub const SIZE: usize = 32 + 4 + 8;
+}
+
+/// A pallet/shipment grouping crates that move together
+#[account]
+pub struct Shipment {
+    pub authority: Pubkey,
+    pub shipment_id: String,
+    pub crate_ids: Vec<Pubkey>,
+}
+
+impl Shipment {
+    pub const MAX_CRATES: usize = 10;
+    pub const MAX_SIZE: usize =
+        32 + // authority
+        4 + 32 + // shipment_id (PDA seed, max 32 bytes)
+        4 + (Self::MAX_CRATES * 32);
+}
+
+/// Fungible bulk storage, see `deposit_to_pool` and `withdraw_from_pool`
+#[account]
+pub struct Pool {
+    pub authority: Pubkey,
+    pub pool_id: String,
+    pub weight: u64, // grams
+}
+
+impl Pool {
+    pub const MAX_SIZE: usize =
+        32 + // authority
+        4 + 32 + // pool_id (PDA seed, max 32 bytes)
+        8;   // weight
+}
+
+/// Claim on a human-readable crate_id, see `reserve_crate_id`
+#[account]
+pub struct IdReservation {
+    pub reserver: Pubkey,
+    pub expires_at: i64,
+}
+
+impl IdReservation {
+    pub const MAX_SIZE: usize = 32 + 8;
+}
+
+/// A procurement commitment crates are fulfilled against via `fulfill_po`
+#[account]
+pub struct PurchaseOrder {
+    pub authority: Pubkey,
+    pub po_number: String,
+    pub supplier: Pubkey,
+    pub expected_grams: u64,
+    pub fulfilled_grams: u64,
+}
+
+impl PurchaseOrder {
+    pub const MAX_SIZE: usize =
+        32 + // authority
+        4 + 32 + // po_number (PDA seed, max 32 bytes)
+        32 + // supplier
+        8 +  // expected_grams
+        8;   // fulfilled_grams
+}
+
+/// Marks a document hash as used; lives at `[b"hash", sha256(hash)]`
+#[account]
+pub struct HashEntry {
+    pub crate_record: Pubkey,
+}
+
+impl HashEntry {
+    pub const SIZE: usize = 32;
+}
+
+/// Defaults shared by crates of one product type, used by `create_from_template`
+#[account]
+pub struct CrateTemplate {
+    pub authority: Pubkey,
+    pub template_id: String,
+    pub product_type: String,
+    pub owner_did: String,
+    pub device_did: String,
+    pub location: String, // origin
+    pub weight_unit: WeightUnit,
+    pub certifications: Vec<Certification>,
+}
+
+impl CrateTemplate {
+    pub const MAX_SIZE: usize =
+        32 + // authority
+        4 + 32 + // template_id (PDA seed, max 32 bytes)
+        4 + CrateRecord::MAX_PRODUCT_TYPE_LEN +
+        4 + 64 + // owner_did
+        4 + 64 + // device_did
+        4 + 64 + // location
+        1 +  // weight_unit
+        4 + (CrateRecord::MAX_CERTIFICATIONS * Certification::MAX_SIZE);
+}
+
+/// Supplier details shared by every crate that references it, see `register_supplier`
+#[account]
+pub struct Supplier {
+    pub supplier_id: String,
+    pub name: String,
+    pub certifications: Vec<String>, // standards, e.g. "ASC"
+    pub contact_cid: String,
+}
+
+impl Supplier {
+    pub const MAX_NAME_LEN: usize = 64;
+    pub const MAX_CERTIFICATIONS: usize = 4;
+    pub const MAX_SIZE: usize =
+        4 + 32 + // supplier_id (PDA seed, max 32 bytes)
+        4 + Self::MAX_NAME_LEN +
+        4 + (Self::MAX_CERTIFICATIONS * (4 + Certification::MAX_STANDARD_LEN)) +
+        4 + CrateRecord::MAX_CID_LEN;
+}
+
+/// Total weight an authority has introduced via `create_crate`
+#[account]
+pub struct AuthorityProduction {
+    pub authority: Pubkey,
+    pub produced_grams: u64,
+    pub production_quota: u64, // 0 = unlimited
+}
+
+impl AuthorityProduction {
+    pub const MAX_SIZE: usize =
+        32 + // authority
+        8 +  // produced_grams
+        8;   // production_quota
+}
+
+/// Recent operation times of an authority, for the sliding-window rate limit
+#[account]
+pub struct RateLimit {
+    pub authority: Pubkey,
+    pub recent_ops: Vec<i64>, // unix seconds, oldest first, only those inside the window
+}
+
+impl RateLimit {
+    pub const MAX_OPS: usize = 32;
+    pub const MAX_SIZE: usize =
+        32 + // authority
+        4 + (Self::MAX_OPS * 8);
+
+    /// Records an operation at `now`, failing once `max_ops_per_window` operations
+    /// already fall inside the trailing `rate_limit_window` seconds
+    pub fn record_op(&mut self, authority: Pubkey, config: &ProgramConfig, now: i64) -> Result<()> {
+        self.authority = authority;
+        if config.max_ops_per_window == 0 {
+            return Ok(());
+        }
+        let window_start = now - config.rate_limit_window as i64;
+        self.recent_ops.retain(|ts| *ts > window_start);
+        require!(
+            self.recent_ops.len() < config.max_ops_per_window as usize,
+            ErrorCode::RateLimitExceeded
+        );
+        self.recent_ops.push(now);
+        Ok(())
+    }
+}
+
+/// One page of processed operation ids for an authority
+#[account]
+pub struct ProcessedBitmap {
+    pub authority: Pubkey,
+    pub page: u64,
+    pub bits: [u8; ProcessedBitmap::PAGE_BYTES],
+}
+
+impl ProcessedBitmap {
+    pub const PAGE_BYTES: usize = 128;
+    pub const IDS_PER_PAGE: u64 = (Self::PAGE_BYTES * 8) as u64;
+    pub const MAX_SIZE: usize =
+        32 + // authority
+        8 +  // page
+        Self::PAGE_BYTES;
+}
+
+/// Basis points that make up a whole parent in bps-based splits
+pub const BPS_DENOMINATOR: u32 = 10_000;
+
+/// Program-wide settings, stored in the `[b"config"]` PDA
+#[account]
+pub struct ProgramConfig {
+    pub admin: Pubkey,
+    pub rounding_policy: RoundingPolicy,
+    pub registered_scales: Vec<Pubkey>,
+    pub strict_hash_uniqueness: bool,
+    pub enforce_timestamp_order: bool,
+    pub registered_labs: Vec<Pubkey>,
+    pub disabled_operations: u8, // bit `1 << OperationType as u8` set = disabled
+    pub transfer_tolerance_bps: u16, // allowed transfer weight change, 0 = exact
+    pub drift_alert_threshold: u32, // |cumulative_weight_delta| alert level, 0 = off
+    pub require_certification_for_transfer: bool,
+    pub max_batch_accounts: u8, // 0 = DEFAULT_MAX_BATCH_ACCOUNTS
+    pub strict_transfer_content: bool, // transfers must keep the parent's hash and ipfs_cid
+    pub mix_tolerance_bps: u16, // allowed mix_and_split output vs input difference, 0 = exact
+    pub void_window_secs: u32, // 0 = DEFAULT_VOID_WINDOW_SECS
+    pub max_expiry_spread: u32, // seconds between earliest and latest mix parent expiry, 0 = unchecked
+    pub strict_expiry_spread: bool, // reject instead of emitting ExpirySpreadWarning
+    pub customs_authorities: Vec<Pubkey>,
+    pub vault_controllers: Vec<Pubkey>, // programs allowed to hold crates in a vault PDA
+    pub strict_split_order: bool, // split_crate checks child accounts against child_keys
+    pub staleness_threshold: u32, // seconds since `timestamp` before a crate is stale, 0 = off
+    pub rate_limit_window: u32, // seconds
+    pub max_ops_per_window: u8, // creates + transfers per authority per window, 0 = unlimited
+    pub required_fields: u8, // `REQUIRE_*` bits that created crates must fill in
+    pub mix_requires_same_lot: bool, // mix_crates rejects parents with differing lot codes
+    pub auto_burn_on_expiry: bool, // check_expiry burns crates past their expiry
+    pub high_value_threshold: u64, // value_cents above which transfers need an approver, 0 = off
+    pub high_value_approvers: Vec<Pubkey>,
+    pub allowed_cid_prefixes: Vec<String>, // approved document sources, empty = any
+    pub distinct_child_authorities: bool, // split_crate children must end up with different owners
+    pub max_transfers: u32, // transfers allowed along one lineage, 0 = unlimited
+    pub operation_statuses: Vec<OperationStatus>, // status new records start in, by operation
+    pub handoff_tolerance_meters: u32, // max distance between handoff reports, 0 = unchecked
+    pub require_id_reservation: bool, // create_crate must consume a `reserve_crate_id` reservation
+    pub reservation_ttl_secs: u32, // 0 = DEFAULT_RESERVATION_TTL_SECS
+    pub require_inspection_for_transfer: bool, // transfers need a passing, unrevoked inspection
+}
+
+impl ProgramConfig {
+    pub const MAX_SCALES: usize = 8;
+    pub const MAX_LABS: usize = 8;
+    pub const MAX_CUSTOMS_AUTHORITIES: usize = 8;
+    pub const MAX_VAULT_CONTROLLERS: usize = 4;
+    pub const MAX_HIGH_VALUE_APPROVERS: usize = 4;
+    pub const MAX_CID_PREFIXES: usize = 4;
+    pub const MAX_CID_PREFIX_LEN: usize = 32;
+    pub const MAX_OPERATION_STATUSES: usize = 8;
+    pub const MAX_SIZE: usize =
+        32 + // admin
+        1 +  // rounding_policy
+        4 + (Self::MAX_SCALES * 32) +
+        1 +  // strict_hash_uniqueness
+        1 +  // enforce_timestamp_order
+        4 + (Self::MAX_LABS * 32) +
+        1 +  // disabled_operations
+        2 +  // transfer_tolerance_bps
+        4 +  // drift_alert_threshold
+        1 +  // require_certification_for_transfer
+        1 +  // max_batch_accounts
+        1 +  // strict_transfer_content
+        2 +  // mix_tolerance_bps
+        4 +  // void_window_secs
+        4 +  // max_expiry_spread
+        1 +  // strict_expiry_spread
+        4 + (Self::MAX_CUSTOMS_AUTHORITIES * 32) +
+        4 + (Self::MAX_VAULT_CONTROLLERS * 32) +
+        1 +  // strict_split_order
+        4 +  // staleness_threshold
+        4 +  // rate_limit_window
+        1 +  // max_ops_per_window
+        1 +  // required_fields
+        1 +  // mix_requires_same_lot
+        1 +  // auto_burn_on_expiry
+        8 +  // high_value_threshold
+        4 + (Self::MAX_HIGH_VALUE_APPROVERS * 32) +
+        4 + (Self::MAX_CID_PREFIXES * (4 + Self::MAX_CID_PREFIX_LEN)) +
+        1 +  // distinct_child_authorities
+        4 +  // max_transfers
+        4 + (Self::MAX_OPERATION_STATUSES * OperationStatus::SIZE) +
+        4 +  // handoff_tolerance_meters
+        1 +  // require_id_reservation
+        4 +  // reservation_ttl_secs
+        1;   // require_inspection_for_transfer
+
+    /// Crate accounts one instruction may deserialize from `remaining_accounts`
+    /// while staying inside the default compute budget
+    pub const DEFAULT_MAX_BATCH_ACCOUNTS: usize = 16;
+    pub const DEFAULT_VOID_WINDOW_SECS: i64 = 15 * 60;
+    pub const DEFAULT_RESERVATION_TTL_SECS: i64 = 10 * 60;
+
+    /// `required_fields` bits
+    pub const REQUIRE_ORIGIN: u8 = 1 << 0; // location
+    pub const REQUIRE_OWNER_DID: u8 = 1 << 1;
+    pub const REQUIRE_DEVICE_DID: u8 = 1 << 2;
+    pub const REQUIRE_DOCUMENT: u8 = 1 << 3; // hash and ipfs_cid
+    pub const REQUIRE_CERTIFICATION: u8 = 1 << 4;
+
+    /// Fails when `record` leaves a field this deployment mandates empty
+    pub fn require_fields(&self, record: &CrateRecord) -> Result<()> {
+        let missing = [
+            (Self::REQUIRE_ORIGIN, record.location.is_empty()),
+            (Self::REQUIRE_OWNER_DID, record.owner_did.is_empty()),
+            (Self::REQUIRE_DEVICE_DID, record.device_did.is_empty()),
+            (Self::REQUIRE_DOCUMENT, record.hash.is_empty()),
+            (Self::REQUIRE_CERTIFICATION, record.certifications.is_empty()),
+        ];
+        for (bit, empty) in missing {
+            require!(self.required_fields & bit == 0 || !empty, ErrorCode::RequiredFieldMissing);
+        }
+        Ok(())
+    }
+
+    pub fn is_operation_enabled(&self, operation: OperationType) -> bool {
+        self.disabled_operations & (1 << operation as u8) == 0
+    }
+
+    /// Moves a just-created record to the status configured for its operation, if any
+    pub fn apply_operation_status(&self, record: &mut CrateRecord, now: i64) -> Result<()> {
+        match self
+            .operation_statuses
+            .iter()
+            .find(|entry| entry.operation == record.operation_type)
+        {
+            Some(entry) => record.set_status(entry.status, now),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails up front instead of running out of compute halfway through a batch
+    pub fn require_batch_fits(&self, len: usize) -> Result<()> {
+        let limit = match self.max_batch_accounts {
+            0 => Self::DEFAULT_MAX_BATCH_ACCOUNTS,
+            max => max as usize,
+        };
+        require!(len <= limit, ErrorCode::BatchTooLargeForComputeBudget);
+        Ok(())
+    }
+
+    /// Crates declared above `high_value_threshold` need a co-signer from `high_value_approvers`
+    pub fn require_high_value_approval(&self, record: &CrateRecord, approver: Option<&Signer>) -> Result<()> {
+        if self.high_value_threshold == 0 || record.value_cents <= self.high_value_threshold {
+            return Ok(());
+        }
+        require!(
+            approver.is_some_and(|approver| {
+                approver.key() != record.authority && self.high_value_approvers.contains(approver.key)
+            }),
+            ErrorCode::HighValueApprovalRequired
+        );
+        Ok(())
+    }
+
+    pub fn void_window_secs(&self) -> i64 {
+        match self.void_window_secs {
+            0 => Self::DEFAULT_VOID_WINDOW_SECS,
+            secs => secs as i64,
+        }
+    }
+
+    pub fn reservation_ttl_secs(&self) -> i64 {
+        match self.reservation_ttl_secs {
+            0 => Self::DEFAULT_RESERVATION_TTL_SECS,
+            secs => secs as i64,
+        }
+    }
+
+    /// Whether a transfer from `parent_weight` to `weight` stays within tolerance
+    pub fn within_transfer_tolerance(&self, parent_weight: u32, weight: u32) -> bool {
+        let delta = parent_weight.abs_diff(weight) as u64;
+        delta * BPS_DENOMINATOR as u64 <= parent_weight as u64 * self.transfer_tolerance_bps as u64
+    }
+}
+
+/// Partial config update; `None` fields are left unchanged
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct ConfigUpdate {
+    pub admin: Option<Pubkey>,
+    pub rounding_policy: Option<RoundingPolicy>,
+    pub registered_scales: Option<Vec<Pubkey>>,
+    pub strict_hash_uniqueness: Option<bool>,
+    pub enforce_timestamp_order: Option<bool>,
+    pub registered_labs: Option<Vec<Pubkey>>,
+    pub disabled_operations: Option<u8>,
+    pub transfer_tolerance_bps: Option<u16>,
+    pub drift_alert_threshold: Option<u32>,
+    pub require_certification_for_transfer: Option<bool>,
+    pub max_batch_accounts: Option<u8>,
+    pub strict_transfer_content: Option<bool>,
+    pub mix_tolerance_bps: Option<u16>,
+    pub void_window_secs: Option<u32>,
+    pub max_expiry_spread: Option<u32>,
+    pub strict_expiry_spread: Option<bool>,
+    pub customs_authorities: Option<Vec<Pubkey>>,
+    pub vault_controllers: Option<Vec<Pubkey>>,
+    pub strict_split_order: Option<bool>,
+    pub staleness_threshold: Option<u32>,
+    pub rate_limit_window: Option<u32>,
+    pub max_ops_per_window: Option<u8>,
+    pub required_fields: Option<u8>,
+    pub mix_requires_same_lot: Option<bool>,
+    pub auto_burn_on_expiry: Option<bool>,
+    pub high_value_threshold: Option<u64>,
+    pub high_value_approvers: Option<Vec<Pubkey>>,
+    pub allowed_cid_prefixes: Option<Vec<String>>,
+    pub distinct_child_authorities: Option<bool>,
+    pub max_transfers: Option<u32>,
+    pub operation_statuses: Option<Vec<OperationStatus>>,
+    pub handoff_tolerance_meters: Option<u32>,
+    pub require_id_reservation: Option<bool>,
+    pub reservation_ttl_secs: Option<u32>,
+    pub require_inspection_for_transfer: Option<bool>,
+}
+
+/// Which children absorb the rounding remainder of a bps split
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingPolicy {
+    LastChild,
+    LargestRemainder,
+    FirstChild,
+}
+
+impl RoundingPolicy {
+    /// Distributes `total` across `bps` shares, always summing exactly to `total`.
+    /// Expects `bps` to be non-empty and sum to `BPS_DENOMINATOR`.
+    pub fn allocate(self, total: u32, bps: &[u16]) -> Vec<u32> {
+        let denominator = BPS_DENOMINATOR as u64;
+        let mut weights = Vec::with_capacity(bps.len());
+        let mut remainders = Vec::with_capacity(bps.len());
+        for share in bps {
+            let scaled = total as u64 * *share as u64;
+            weights.push((scaled / denominator) as u32);
+            remainders.push(scaled % denominator);
+        }
+
+        let leftover = total - weights.iter().sum::<u32>();
+        match self {
+            RoundingPolicy::LastChild => {
+                let last = weights.len() - 1;
+                weights[last] += leftover;
+            }
+            RoundingPolicy::FirstChild => weights[0] += leftover,
+            RoundingPolicy::LargestRemainder => {
+                // Each child's fractional part is < 1, so leftover < bps.len().
+                // Equal remainders go to the lowest child index first, so every
+                // client derives the same allocation.
+                let mut order: Vec<usize> = (0..bps.len()).collect();
+                order.sort_by(|a, b| remainders[*b].cmp(&remainders[*a]).then(a.cmp(b)));
+                for index in order.into_iter().take(leftover as usize) {
+                    weights[index] += 1;
+                }
+            }
+        }
+        weights
+    }
+}
+
+/// One child of `split_to_many`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SplitRecipient {
+    pub crate_id: String,
+    pub authority: Pubkey,
+    pub weight: u32,
+}
+
+/// One child of `split_crate_mixed_spec`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub enum ChildSpec {
+    Absolute(u32),
+    Percent(u16), // basis points of the weight left after the absolute children
+}
+
+impl ChildSpec {
+    /// Resolves `specs` to child weights summing exactly to `total`; percent
+    /// rounding follows `policy`
+    pub fn resolve(specs: &[ChildSpec], total: u32, policy: RoundingPolicy) -> Result<Vec<u32>> {
+        let mut absolute: u32 = 0;
+        let mut bps: Vec<u16> = Vec::new();
+        for spec in specs {
+            match spec {
+                ChildSpec::Absolute(weight) => {
+                    absolute = absolute.checked_add(*weight).ok_or(ErrorCode::WeightOverflow)?
+                }
+                ChildSpec::Percent(share) => bps.push(*share),
+            }
+        }
+        let remainder = total.checked_sub(absolute).ok_or(ErrorCode::SplitOverAllocated)?;
+        let total_bps: u32 = bps.iter().map(|share| *share as u32).sum();
+        require!(total_bps <= BPS_DENOMINATOR, ErrorCode::SplitOverAllocated);
+
+        let mut percent_weights = if bps.is_empty() {
+            require!(remainder == 0, ErrorCode::SplitWeightMismatch);
+            Vec::new()
+        } else {
+            require!(total_bps == BPS_DENOMINATOR, ErrorCode::InvalidBpsTotal);
+            policy.allocate(remainder, &bps)
+        }
+        .into_iter();
+
+        Ok(specs
+            .iter()
+            .map(|spec| match spec {
+                ChildSpec::Absolute(weight) => *weight,
+                ChildSpec::Percent(_) => percent_weights.next().unwrap_or_default(),
+            })
+            .collect())
+    }
+}
+
+// ===================
+// EVENTS
+// ===================
+//
+// Every event starts with the same header so indexers can filter on raw log data
+// at fixed offsets without decoding the payload:
+//
+//   [0..8)   event discriminator
+//   [8..40)  authority             (crate authority at emission time)
+//   [40..72) crate_record
+//   [72]     program_event_version
+//   [73..)   event-specific fields, Borsh encoded
+//
+// Fields are only ever appended; any other layout change bumps the version.
+
+/// Current value of `program_event_version`
+pub const PROGRAM_EVENT_VERSION: u8 = 1;
+
+#[event]
+pub struct UnderInsured {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub coverage_cents: u64,
+    pub value_cents: u64,
+}
+
+#[event]
+pub struct DeliveryConfirmed {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub recipient: Pubkey,
+    pub delivered_at: i64,
+    pub sealed: bool,
+}
+
+#[event]
+pub struct TamperDetected {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub stored_lineage_hash: [u8; 32],
+    pub expected_lineage_hash: [u8; 32],
+}
+
+#[event]
+pub struct WeightDriftAlert {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub cumulative_weight_delta: i32,
+    pub threshold: u32,
+}
+
+#[event]
+pub struct ReceivingDiscrepancy {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub recorded_weight: u32,
+    pub measured_weight: u32,
+    pub delta: i64,
+}
+
+#[event]
+pub struct ExpirySpreadWarning {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub spread: i64,
+    pub max_spread: u32,
+}
+
+#[event]
+pub struct StaleInventoryFlagged {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub age: i64,
+    pub threshold: u32,
+}
+
+#[event]
+pub struct AutoBurned {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub expiry: i64,
+}
+
+#[event]
+pub struct OrganicStatusLost {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+}
+
+#[event]
+pub struct ExcursionRecorded {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub min_temp: i16,
+    pub max_temp: i16,
+    pub duration_seconds: u32,
+}
+
+#[event]
+pub struct HandlingViolation {
+    pub authority: Pubkey,
+    pub crate_record: Pubkey,
+    pub program_event_version: u8,
+    pub tilt_degrees: u8,
+    pub stack_height: u8,
+    pub timestamp: i64,
+}
+
+// ===================
+// HELPERS
+// ===================
+
+/// Upper bound on crates visited by a single lineage walk
+pub const MAX_LINEAGE_HOPS: usize = 16;
+/// One direction bit per proof step
+pub const MAX_MERKLE_PROOF_LEN: usize = 32;
+
+/// Walks `parent_crates` upward from `start` looking for `target`.
+/// Only crates supplied in `accounts` can be expanded; others are treated as roots.
+fn ancestry_contains<'info>(
+    start: &[Pubkey],
+    target: Pubkey,
+    accounts: &'info [AccountInfo<'info>],
+) -> Result<bool> {
+    let mut queue: Vec<Pubkey> = start.to_vec();
+    let mut visited: Vec<Pubkey> = Vec::new();
+
+    while let Some(key) = queue.pop() {
+        if key == target {
+            return Ok(true);
+        }
+        if visited.contains(&key) {
+            continue;
+        }
+        require!(visited.len() < MAX_LINEAGE_HOPS, ErrorCode::LineageWalkTooDeep);
+        visited.push(key);
+
+        if let Some(info) = accounts.iter().find(|info| info.key() == key) {
+            let record: Account<CrateRecord> = Account::try_from(info)?;
+            queue.extend(record.parent_crates.iter().copied());
+        }
+    }
+    Ok(false)
+}
+
+/// Shared body of `create_crate` and `create_from_template`
+fn init_crate(
+    accounts: &mut CreateCrate,
+    crate_id: String,
+    crate_did: String,
+    owner_did: String,
+    device_did: String,
+    location: String,
+    weight: u32,
+    timestamp: i64,
+    hash: String,
+    ipfs_cid: String,
+    weight_unit: WeightUnit,
+) -> Result<()> {
+    // A crate without document refs has no hash to register
+    if accounts.config.strict_hash_uniqueness && !hash.is_empty() {
+        let hash_entry = accounts
+            .hash_entry
+            .as_ref()
+            .ok_or(ErrorCode::HashEntryRequired)?;
+        register_hash(
+            hash_entry,
+            &accounts.authority,
+            &accounts.system_program,
+            &hash,
+            accounts.crate_record.key(),
+        )?;
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    accounts.rate_limit.record_op(accounts.authority.key(), &accounts.config, now)?;
+
+    // A live reservation blocks everyone but its reserver, whether or not reservations are required
+    let required = accounts.config.require_id_reservation;
+    let reservation = accounts.reservation.to_account_info();
+    if reservation.owner == &crate::ID && !reservation.data_is_empty() {
+        let held = IdReservation::try_deserialize(&mut &reservation.try_borrow_data()?[..])?;
+        let held_by_creator = held.reserver == accounts.authority.key();
+        let live = now < held.expires_at;
+        require!(held_by_creator || (!live && !required), ErrorCode::CrateIdNotReserved);
+        require!(live || !required, ErrorCode::ReservationExpired);
+        if held_by_creator {
+            close_account(&reservation, &accounts.authority.to_account_info())?;
+        }
+    } else {
+        require!(!required, ErrorCode::CrateIdNotReserved);
+    }
+
+    require_document_refs(&accounts.config, &hash, &ipfs_cid)?;
+
+    let record = &mut accounts.crate_record;
+    record.crate_id = crate_id;
+    record.crate_did = crate_did;
+    record.owner_did = owner_did;
+    record.device_did = device_did;
+    record.location = location;
+    record.weight = weight;
+    record.timestamp = timestamp;
+    record.hash = hash;
+    record.ipfs_cid = ipfs_cid;
+    record.authority = accounts.authority.key();
+    record.parent_crates = Vec::new();
+    record.child_crates = Vec::new();
+    record.parent_weights = Vec::new();
+    record.split_distribution = Vec::new();
+    record.operation_type = OperationType::Created;
+    record.weight_unit = weight_unit;
+    record.lineage_hash = lineage_hash(&record.hash, &[]);
+    record.created_at = now;
+    record.supplier = accounts.supplier.as_ref().map(|supplier| supplier.key()).unwrap_or_default();
+    accounts.config.apply_operation_status(record, now)?;
+
+    let production = &mut accounts.production;
+    production.authority = accounts.authority.key();
+    production.produced_grams = production.produced_grams.saturating_add(weight_unit.to_grams(weight));
+    require!(
+        production.production_quota == 0 || production.produced_grams <= production.production_quota,
+        ErrorCode::ProductionQuotaExceeded
+    );
+    Ok(())
+}
+
+/// Shared body of `transfer_ownership` and `transfer_with_status`
+fn transfer_record(
+    accounts: &mut TransferOwnership,
+    crate_id: String,
+    crate_did: String,
+    owner_did: String,
+    device_did: String,
+    location: String,
+    weight: u32,
+    timestamp: i64,
+    hash: String,
+    ipfs_cid: String,
+    carbon_grams: u64,
+    vault_controller: Option<Pubkey>,
+) -> Result<()> {
+    let parent = &accounts.parent_crate;
+
+    require_keys_eq!(
+        accounts.authority.key(),
+        parent.authority,
+        ErrorCode::UnauthorizedUpdate
+    );
+    parent.prepare_transfer(&accounts.config)?;
+    require!(
+        accounts.config.is_operation_enabled(OperationType::Transferred),
+        ErrorCode::OperationDisabled
+    );
+    accounts.config.require_high_value_approval(parent, accounts.approver.as_ref())?;
+
+    let config = &accounts.config;
+    require!(
+        config.within_transfer_tolerance(parent.weight, weight),
+        ErrorCode::WeightMismatchOnTransfer
+    );
+    let weight_delta = i32::try_from(weight as i64 - parent.weight as i64)
+        .map_err(|_| ErrorCode::WeightOverflow)?;
+    let cumulative_weight_delta = parent.cumulative_weight_delta.saturating_add(weight_delta);
+    require!(
+        !config.enforce_timestamp_order || timestamp >= parent.timestamp,
+        ErrorCode::TimestampBeforeParent
+    );
+    require!(
+        !config.strict_transfer_content || (hash == parent.hash && ipfs_cid == parent.ipfs_cid),
+        ErrorCode::TransferAlteredContent
+    );
+    require!(
+        !config.require_certification_for_transfer
+            || parent.has_valid_certification(Clock::get()?.unix_timestamp),
+        ErrorCode::CertificationRequired
+    );
+    require!(
+        !config.require_inspection_for_transfer || parent.has_passing_inspection(),
+        ErrorCode::InspectionRequired
+    );
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        parent.min_hold_seconds == 0
+            || now.saturating_sub(parent.timestamp) >= parent.min_hold_seconds as i64,
+        ErrorCode::HoldPeriodNotMet
+    );
+    let drift_alert_threshold = config.drift_alert_threshold;
+    accounts.rate_limit.record_op(accounts.authority.key(), config, now)?;
+    let (authority, vault_controller) = match vault_controller {
+        Some(controller) => (vault_address(config, &controller)?, controller),
+        None => (accounts.authority.key(), parent.vault_controller),
+    };
+
+    require_document_refs(&accounts.config, &hash, &ipfs_cid)?;
+    // Locations are free-form text elsewhere, so a leg without coordinates adds nothing
+    let leg_meters = match (parse_coordinates(&parent.location), parse_coordinates(&location)) {
+        (Some(from), Some(to)) => haversine_meters(from, to),
+        _ => 0,
+    };
+    let total_distance_meters = parent.total_distance_meters.saturating_add(leg_meters);
+
+    let record = &mut accounts.crate_record;
+    record.crate_id = crate_id;
+    record.crate_did = crate_did;
+    record.owner_did = owner_did;
+    record.device_did = device_did;
+    record.location = location;
+    record.weight = weight;
+    record.timestamp = timestamp;
+    record.hash = hash;
+    record.ipfs_cid = ipfs_cid;
+    record.authority = authority;
+    record.vault_controller = vault_controller;
+    record.parent_crates = vec![parent.key()];
+    record.child_crates = Vec::new();
+    record.parent_weights = vec![parent.weight];
+    record.weight_unit = parent.weight_unit;
+    record.split_distribution = Vec::new();
+    record.operation_type = OperationType::Transferred;
+    record.certifications = parent.certifications.clone();
+    record.lot_code = parent.lot_code.clone();
+    record.expiry = parent.expiry;
+    record.excursion_flag = parent.excursion_flag;
+    record.handling_violation = parent.handling_violation;
+    record.max_tilt_degrees = parent.max_tilt_degrees;
+    record.max_stack_height = parent.max_stack_height;
+    record.dpp_id = parent.dpp_id.clone();
+    record.production_method = parent.production_method;
+    record.mixed_dpp_ids = parent.mixed_dpp_ids.clone();
+    record.display_decimals = parent.display_decimals;
+    record.carbon_grams = parent.carbon_grams.saturating_add(carbon_grams);
+    record.total_distance_meters = total_distance_meters;
+    record.transfer_count = parent.transfer_count.saturating_add(1);
+    record.weight_delta = weight_delta;
+    record.cumulative_weight_delta = cumulative_weight_delta;
+    record.lineage_hash = lineage_hash(&record.hash, &[parent.lineage_hash]);
+    require!(record.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+    let child = record.key();
+
+    if drift_alert_threshold > 0 && cumulative_weight_delta.unsigned_abs() > drift_alert_threshold {
+        emit!(WeightDriftAlert {
+            authority: record.authority,
+            crate_record: record.key(),
+            program_event_version: PROGRAM_EVENT_VERSION,
+            cumulative_weight_delta,
+            threshold: drift_alert_threshold,
+        });
+    }
+
+    accounts.parent_crate.consume_into(child, weight)
+}
+
+/// Shared body of every split: fills `record` as the child of `parent` holding `weight`,
+/// one of `child_crates`/`split_distribution` (both empty for a split-off child)
+fn init_split_child(
+    record: &mut CrateRecord,
+    parent: &Account<CrateRecord>,
+    config: &ProgramConfig,
+    crate_id: String,
+    crate_did: String,
+    owner_did: String,
+    device_did: String,
+    location: String,
+    weight: u32,
+    timestamp: i64,
+    hash: String,
+    ipfs_cid: String,
+    authority: Pubkey,
+    child_crates: Vec<Pubkey>,
+    split_distribution: Vec<u32>,
+    carbon_grams: u64,
+) -> Result<()> {
+    record.crate_id = crate_id;
+    record.crate_did = crate_did;
+    record.owner_did = owner_did;
+    record.device_did = device_did;
+    record.location = location;
+    record.weight = weight;
+    record.timestamp = timestamp;
+    record.hash = hash;
+    record.ipfs_cid = ipfs_cid;
+    record.authority = authority;
+    record.parent_crates = vec![parent.key()];
+    record.child_crates = child_crates;
+    record.parent_weights = vec![parent.weight];
+    record.parent_weight_at_split = parent.weight;
+    record.dpp_id = parent.dpp_id.clone();
+    record.production_method = parent.production_method;
+    record.mixed_dpp_ids = parent.mixed_dpp_ids.clone();
+    record.display_decimals = parent.display_decimals;
+    record.weight_unit = parent.weight_unit;
+    record.split_distribution = split_distribution;
+    record.operation_type = OperationType::Split;
+    record.transfer_count = parent.transfer_count;
+    record.lot_code = parent.lot_code.clone();
+    record.expiry = parent.expiry;
+    record.min_hold_seconds = parent.hold_remaining_at(timestamp);
+    record.carbon_grams = parent.carbon_share(weight).saturating_add(carbon_grams);
+    record.lineage_hash = lineage_hash(&record.hash, &[parent.lineage_hash]);
+    require!(record.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+    let now = Clock::get()?.unix_timestamp;
+    record.created_at = now;
+    config.apply_operation_status(record, now)
+}
+
+/// Checks the split children in `children` that already exist end up with different
+/// authorities from each other and from `current_authority`, the owner of the child
+/// being created. Children not created yet are skipped.
+fn require_distinct_child_authorities(
+    children: &[AccountInfo],
+    child_keys: &[Pubkey],
+    current_key: Pubkey,
+    current_authority: Pubkey,
+) -> Result<()> {
+    let mut authorities = vec![current_authority];
+    for child_info in children {
+        require!(child_keys.contains(child_info.key), ErrorCode::ChildNotInSplit);
+        if *child_info.key == current_key || child_info.owner != &crate::ID {
+            continue;
+        }
+        let child = CrateRecord::try_deserialize(&mut &child_info.try_borrow_data()?[..])?;
+        let authority = child.eventual_authority();
+        require!(!authorities.contains(&authority), ErrorCode::DuplicateChildAuthority);
+        authorities.push(authority);
+    }
+    Ok(())
+}
+
+/// Aggregates of the parents of a mix, all weights normalized to grams
+struct ParentSummary {
+    total_grams: u64,
+    weights: Vec<u32>,
+    latest_timestamp: i64,
+    lineage_hashes: Vec<[u8; 32]>,
+    carbon_grams: u64,
+    earliest_expiry: i64, // over parents with an expiry, 0 if none has one
+    latest_expiry: i64,
+    lot_code: String, // of the first parent
+    same_lot: bool,   // every parent has `lot_code`, and it is not empty
+    any_excursion: bool,
+    any_handling_violation: bool,
+    dpp_ids: Vec<String>, // distinct, from `dpp_id` and `mixed_dpp_ids`
+    display_decimals: u8, // finest parent precision
+    production_method: ProductionMethod, // shared by every parent, else Conventional
+    any_organic: bool,
+    blended_grade: u16, // from the caller's per-parent grades, 0 without any
+    transfer_count: u32, // highest among the parents
+}
+
+/// Validation shared by `mix_crates` and `mix_and_split` for the mix into `crate_record`.
+/// `accounts` must be exactly `parent_keys`, in canonical order; `parent_grades`, if not
+/// empty, holds one grade per parent in the same order. `split_keys` are the children of
+/// a mix-and-split, empty for a plain mix.
+fn prepare_mix<'info>(
+    config: &ProgramConfig,
+    accounts: &'info [AccountInfo<'info>],
+    parent_keys: &[Pubkey],
+    parent_grades: &[u16],
+    split_keys: &[Pubkey],
+    authority: Pubkey,
+    crate_record: Pubkey,
+    timestamp: i64,
+) -> Result<ParentSummary> {
+    require!(parent_keys.len() >= 2, ErrorCode::MixRequiresMultipleParents);
+    require!(parent_keys.len() <= CrateRecord::MAX_PARENTS, ErrorCode::TooManyParents);
+    // Canonical order keeps lineage hashes reproducible and rules out duplicates
+    require!(
+        parent_keys.windows(2).all(|pair| pair[0] < pair[1]),
+        ErrorCode::ParentsNotSorted
+    );
+    config.require_batch_fits(accounts.len())?;
+    require_parent_accounts(accounts, parent_keys)?;
+
+    let mut parents = summarize_parents(config, accounts, authority, !split_keys.is_empty())?;
+    require!(
+        !config.enforce_timestamp_order || timestamp >= parents.latest_timestamp,
+        ErrorCode::TimestampBeforeParent
+    );
+    check_expiry_spread(config, &parents, authority, crate_record)?;
+    require!(!config.mix_requires_same_lot || parents.same_lot, ErrorCode::LotCodeMismatch);
+    require!(
+        parent_grades.is_empty() || parent_grades.len() == parents.weights.len(),
+        ErrorCode::GradeCountMismatch
+    );
+    parents.blended_grade = weighted_average(parent_grades, &parents.weights);
+    Ok(parents)
+}
+
+/// Fills what a mix child inherits from its `parents`, once its `hash` is set, and
+/// reports a lost organic status
+fn inherit_mix_parents(record: &mut Account<CrateRecord>, parents: ParentSummary) -> Result<()> {
+    record.parent_weights = parents.weights;
+    record.weight_unit = WeightUnit::Grams;
+    record.blended_grade = parents.blended_grade;
+    record.expiry = parents.earliest_expiry;
+    record.excursion_flag = parents.any_excursion;
+    record.handling_violation = parents.any_handling_violation;
+    record.mixed_dpp_ids = parents.dpp_ids;
+    record.display_decimals = parents.display_decimals;
+    record.production_method = parents.production_method;
+    record.transfer_count = parents.transfer_count;
+    if parents.same_lot {
+        record.lot_code = parents.lot_code;
+    }
+    record.lineage_hash = lineage_hash(&record.hash, &parents.lineage_hashes);
+    require!(record.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+
+    if parents.any_organic && record.production_method != ProductionMethod::Organic {
+        emit!(OrganicStatusLost {
+            authority: record.authority,
+            crate_record: record.key(),
+            program_event_version: PROGRAM_EVENT_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// Applies `consume` to every mix parent in `accounts` and writes them back
+fn consume_mix_parents<'info>(
+    accounts: &'info [AccountInfo<'info>],
+    mut consume: impl FnMut(&mut CrateRecord) -> Result<()>,
+) -> Result<()> {
+    for parent_info in accounts {
+        let mut parent: Account<CrateRecord> = Account::try_from(parent_info)?;
+        consume(&mut parent)?;
+        require!(parent.lineage_invariants_hold(), ErrorCode::LineageInvariantBroken);
+        parent.exit(&crate::ID)?;
+    }
+    Ok(())
+}
+
+/// Checks the parent `accounts` of a mix are exactly `parent_keys`, in order, so the
+/// summary reads the same crates the child records as its parents
+fn require_parent_accounts(accounts: &[AccountInfo], parent_keys: &[Pubkey]) -> Result<()> {
+    require!(
+        accounts.len() == parent_keys.len()
+            && accounts.iter().zip(parent_keys).all(|(info, key)| info.key == key),
+        ErrorCode::ParentAccountMismatch
+    );
+    Ok(())
+}
+
+/// Loads and validates every parent in `accounts` for a mix signed by `authority`. The
+/// parents of a mix-and-split (`split`) are consumed by its first child, so later
+/// children leave that to `consume_into_split`.
+fn summarize_parents<'info>(
+    config: &ProgramConfig,
+    accounts: &'info [AccountInfo<'info>],
+    authority: Pubkey,
+    split: bool,
+) -> Result<ParentSummary> {
+    let mut summary = ParentSummary {
+        total_grams: 0,
+        weights: Vec::new(),
+        latest_timestamp: i64::MIN,
+        lineage_hashes: Vec::new(),
+        carbon_grams: 0,
+        earliest_expiry: 0,
+        latest_expiry: 0,
+        lot_code: String::new(),
+        same_lot: true,
+        any_excursion: false,
+        any_handling_violation: false,
+        dpp_ids: Vec::new(),
+        display_decimals: 0,
+        production_method: ProductionMethod::Unspecified,
+        any_organic: false,
+        blended_grade: 0,
+        transfer_count: 0,
+    };
+    for (i, parent_info) in accounts.iter().enumerate() {
+        let parent: Account<CrateRecord> = Account::try_from(parent_info)?;
+        require_keys_eq!(parent.authority, authority, ErrorCode::UnauthorizedUpdate);
+        if split {
+            parent.prepare_split_derivation(config)?;
+        } else {
+            parent.prepare_derivation(config)?;
+        }
+
+        // Parents may use different units, so everything is summed in grams
+        let parent_grams = parent.weight_unit.to_grams(parent.weight);
+        summary.total_grams = summary
+            .total_grams
+            .checked_add(parent_grams)
+            .ok_or(ErrorCode::WeightOverflow)?;
+        summary
+            .weights
+            .push(u32::try_from(parent_grams).map_err(|_| ErrorCode::WeightOverflow)?);
+        summary.latest_timestamp = summary.latest_timestamp.max(parent.timestamp);
+        summary.lineage_hashes.push(parent.lineage_hash);
+        summary.carbon_grams = summary.carbon_grams.saturating_add(parent.carbon_grams);
+        if parent.expiry != 0 {
+            summary.earliest_expiry = match summary.earliest_expiry {
+                0 => parent.expiry,
+                earliest => earliest.min(parent.expiry),
+            };
+            summary.latest_expiry = summary.latest_expiry.max(parent.expiry);
+        }
+        if i == 0 {
+            summary.lot_code = parent.lot_code.clone();
+            summary.production_method = parent.production_method;
+        } else {
+            if parent.lot_code != summary.lot_code {
+                summary.same_lot = false;
+            }
+            // Mixing methods is a conservative downgrade
+            if parent.production_method != summary.production_method {
+                summary.production_method = ProductionMethod::Conventional;
+            }
+        }
+        // A parent without a lot code can't vouch for the lot of the mix
+        summary.same_lot &= !parent.lot_code.is_empty();
+        summary.any_organic |= parent.production_method == ProductionMethod::Organic;
+        summary.transfer_count = summary.transfer_count.max(parent.transfer_count);
+        summary.any_excursion |= parent.excursion_flag;
+        summary.any_handling_violation |= parent.handling_violation;
+        summary.display_decimals = summary.display_decimals.max(parent.display_decimals);
+        for dpp_id in std::iter::once(&parent.dpp_id).chain(parent.mixed_dpp_ids.iter()) {
+            if !dpp_id.is_empty() && !summary.dpp_ids.contains(dpp_id) {
+                summary.dpp_ids.push(dpp_id.clone());
+            }
+        }
+    }
+    require!(summary.dpp_ids.len() <= CrateRecord::MAX_MIXED_DPP_IDS, ErrorCode::TooManyDppIds);
+    Ok(summary)
+}
+
+/// `sum(value_i * weight_i) / sum(weight_i)`, rounded half up; 0 without any weight
+fn weighted_average(values: &[u16], weights: &[u32]) -> u16 {
+    let total: u64 = weights.iter().map(|w| *w as u64).sum();
+    if values.is_empty() || total == 0 {
+        return 0;
+    }
+    let weighted: u128 = values
+        .iter()
+        .zip(weights.iter())
+        .map(|(value, weight)| *value as u128 * *weight as u128)
+        .sum();
+    // The mean of u16 values always fits in u16
+    ((weighted + total as u128 / 2) / total as u128) as u16
+}
+
+/// Parses a `"lat,lon"` location in decimal degrees into microdegrees.
+/// Digits past the sixth decimal place are truncated.
+fn parse_coordinates(location: &str) -> Option<(i64, i64)> {
+    let (lat, lon) = location.split_once(',')?;
+    let lat = parse_microdegrees(lat.trim())?;
+    let lon = parse_microdegrees(lon.trim())?;
+    (lat.abs() <= 90_000_000 && lon.abs() <= 180_000_000).then_some((lat, lon))
+}
+
+fn parse_microdegrees(value: &str) -> Option<i64> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || whole.len() > 3 || !is_digits(whole) || !is_digits(fraction) {
+        return None;
+    }
+    let mut micro = whole.parse::<i64>().ok()? * 1_000_000;
+    let mut place = 100_000;
+    for digit in fraction.bytes().take(6) {
+        micro += (digit - b'0') as i64 * place;
+        place /= 10;
+    }
+    Some(if negative { -micro } else { micro })
+}
+
+/// Fixed-point scale for angles in radians
+const ANGLE_SCALE: i128 = 1_000_000_000_000;
+const PI_FIXED: i128 = 3_141_592_653_590;
+const EARTH_RADIUS_METERS: i128 = 6_371_000;
+
+/// `sin(x)` for `x` in radians scaled by `ANGLE_SCALE`, via a Taylor series after
+/// reducing `x` into `[-pi, pi]`
+fn sin_fixed(x: i128) -> i128 {
+    let x = (x + PI_FIXED).rem_euclid(2 * PI_FIXED) - PI_FIXED;
+    let x_squared = x * x / ANGLE_SCALE;
+    let mut term = x;
+    let mut sum = x;
+    for n in 1..12 {
+        term = -term * x_squared / ANGLE_SCALE / ((2 * n) * (2 * n + 1));
+        sum += term;
+    }
+    sum
+}
+
+fn cos_fixed(x: i128) -> i128 {
+    sin_fixed(x + PI_FIXED / 2)
+}
+
+/// `asin(x)` for `x` in `[0, 1]` scaled by `ANGLE_SCALE`, by Newton's method on `sin_fixed`.
+/// Near 1, where `cos` vanishes, it solves the complement `pi/2 - asin(sqrt(1 - x^2))`.
+fn asin_fixed(x: i128) -> i128 {
+    if x > ANGLE_SCALE * 7 / 10 {
+        let complement = isqrt((ANGLE_SCALE * ANGLE_SCALE - x * x).max(0));
+        return PI_FIXED / 2 - asin_fixed(complement);
+    }
+    let mut y = x;
+    for _ in 0..8 {
+        let step = (sin_fixed(y) - x) * ANGLE_SCALE / cos_fixed(y);
+        if step == 0 {
+            break;
+        }
+        y -= step;
+    }
+    y
+}
+
+/// Great-circle distance between two microdegree coordinates, by the haversine formula
+fn haversine_meters(from: (i64, i64), to: (i64, i64)) -> u64 {
+    let radians = |micro: i64| micro as i128 * PI_FIXED / 180_000_000;
+    let (lat1, lon1) = (radians(from.0), radians(from.1));
+    let (lat2, lon2) = (radians(to.0), radians(to.1));
+    let half_dlat = sin_fixed((lat2 - lat1) / 2);
+    let half_dlon = sin_fixed((lon2 - lon1) / 2);
+    let cos_product = cos_fixed(lat1) * cos_fixed(lat2) / ANGLE_SCALE;
+    let a = half_dlat * half_dlat / ANGLE_SCALE
+        + cos_product * (half_dlon * half_dlon / ANGLE_SCALE) / ANGLE_SCALE;
+    let central_angle = 2 * asin_fixed(isqrt(a.clamp(0, ANGLE_SCALE) * ANGLE_SCALE));
+    (EARTH_RADIUS_METERS * central_angle / ANGLE_SCALE) as u64
+}
+
+/// Integer square root by Newton's method
+fn isqrt(value: i128) -> i128 {
+    if value < 2 {
+        return value;
+    }
+    let mut x = value;
+    let mut y = (x + 1) / 2;
+    while y < x {
+        x = y;
+        y = (x + value / x) / 2;
+    }
+    x
+}
+
+/// A record references its document by both `hash` and `ipfs_cid`, or by neither.
+/// With `allowed_cid_prefixes` configured, the CID must start with one of them.
+fn require_document_refs(config: &ProgramConfig, hash: &str, ipfs_cid: &str) -> Result<()> {
+    require!(hash.is_empty() == ipfs_cid.is_empty(), ErrorCode::InconsistentDocumentRefs);
+    require!(
+        ipfs_cid.is_empty()
+            || config.allowed_cid_prefixes.is_empty()
+            || config.allowed_cid_prefixes.iter().any(|prefix| ipfs_cid.starts_with(prefix.as_str())),
+        ErrorCode::DisallowedCidSource
+    );
+    Ok(())
+}
+
+pub const SPL_TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+
+/// Mint authority of an initialized SPL Token mint, read straight from its 82-byte
+/// layout: `COption<Pubkey>` authority at 0..36, `is_initialized` at 45
+fn mint_authority(mint: &AccountInfo) -> Result<Option<Pubkey>> {
+    require_keys_eq!(*mint.owner, SPL_TOKEN_PROGRAM_ID, ErrorCode::InvalidMint);
+    let data = mint.try_borrow_data()?;
+    require!(data.len() == 82 && data[45] == 1, ErrorCode::InvalidMint);
+    Ok(match data[0..4] {
+        [1, 0, 0, 0] => Some(Pubkey::try_from(&data[4..36]).map_err(|_| ErrorCode::InvalidMint)?),
+        _ => None,
+    })
+}
+
+/// Seed of the custody vault PDA each approved controller program derives
+pub const VAULT_SEED: &[u8] = b"vault";
+
+/// Vault PDA of `controller`, which must be an approved vault controller
+fn vault_address(config: &ProgramConfig, controller: &Pubkey) -> Result<Pubkey> {
+    require!(
+        config.vault_controllers.contains(controller),
+        ErrorCode::UnapprovedVaultController
+    );
+    Ok(Pubkey::find_program_address(&[VAULT_SEED], controller).0)
+}
+
+/// Rejects (strict) or flags a mix whose parent expiries lie further apart than
+/// `max_expiry_spread`; parents without an expiry are ignored
+fn check_expiry_spread(
+    config: &ProgramConfig,
+    parents: &ParentSummary,
+    authority: Pubkey,
+    crate_record: Pubkey,
+) -> Result<()> {
+    let spread = parents.latest_expiry - parents.earliest_expiry;
+    if config.max_expiry_spread == 0 || spread <= config.max_expiry_spread as i64 {
+        return Ok(());
+    }
+    require!(!config.strict_expiry_spread, ErrorCode::ExpirySpreadTooLarge);
+    emit!(ExpirySpreadWarning {
+        authority,
+        crate_record,
+        program_event_version: PROGRAM_EVENT_VERSION,
+        spread,
+        max_spread: config.max_expiry_spread,
+    });
+    Ok(())
+}
+
+/// Drops repeated `keys`, keeping the first occurrence. `weights` is either empty
+/// or aligned with `keys`, and stays that way.
+fn dedupe_edges(keys: &[Pubkey], weights: &[u32]) -> (Vec<Pubkey>, Vec<u32>) {
+    let mut kept_keys = Vec::new();
+    let mut kept_weights = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        if kept_keys.contains(key) {
+            continue;
+        }
+        kept_keys.push(*key);
+        if let Some(weight) = weights.get(i) {
+            kept_weights.push(*weight);
+        }
+    }
+    (kept_keys, kept_weights)
+}
+
+/// Closes a program-owned account read without a typed wrapper, refunding its rent to
+/// `destination`. Mirrors what Anchor's `close` constraint does.
+fn close_account<'info>(info: &AccountInfo<'info>, destination: &AccountInfo<'info>) -> Result<()> {
+    **destination.try_borrow_mut_lamports()? += info.lamports();
+    **info.try_borrow_mut_lamports()? = 0;
+    info.assign(&system_program::ID);
+    info.resize(0).map_err(Into::into)
+}
+
+/// Creates the `HashEntry` PDA for `hash`, failing if the hash was registered before.
+/// Seeds use the sha256 digest since document hashes may exceed the 32-byte seed limit.
+/// Lamports sent to the address beforehand don't block the creation: the entry is topped
+/// up to rent exemption, then allocated and assigned.
+fn register_hash<'info>(
+    hash_entry: &UncheckedAccount<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    hash: &str,
+    crate_record: Pubkey,
+) -> Result<()> {
+    let digest = hashv(&[hash.as_bytes()]).to_bytes();
+    let (address, bump) = Pubkey::find_program_address(&[b"hash", &digest], &crate::ID);
+    require_keys_eq!(hash_entry.key(), address, ErrorCode::InvalidHashEntry);
+    require!(
+        hash_entry.owner != &crate::ID || hash_entry.data_is_empty(),
+        ErrorCode::DuplicateHash
+    );
+
+    let space = 8 + HashEntry::SIZE;
+    let top_up = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(hash_entry.lamports());
+    if top_up > 0 {
+        transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                Transfer {
+                    from: payer.to_account_info(),
+                    to: hash_entry.to_account_info(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+    let seeds: &[&[&[u8]]] = &[&[b"hash", &digest, &[bump]]];
+    allocate(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            Allocate { account_to_allocate: hash_entry.to_account_info() },
+            seeds,
+        ),
+        space as u64,
+    )?;
+    assign(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            Assign { account_to_assign: hash_entry.to_account_info() },
+            seeds,
+        ),
+        &crate::ID,
+    )?;
+
+    let mut data = hash_entry.try_borrow_mut_data()?;
+    HashEntry { crate_record }.try_serialize(&mut &mut data[..])?;
+    Ok(())
+}
+
+/// Allocates `account` as an empty `CrateRecord` owned by this program, the way `init`
+/// would. `account` must sign the transaction.
+fn create_crate_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let space = 8 + CrateRecord::MAX_SIZE;
+    create_account(
+        CpiContext::new(
+            system_program.to_account_info(),
+            CreateAccount {
+                from: payer.to_account_info(),
+                to: account.clone(),
+            },
+        ),
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        &crate::ID,
+    )
+}
+
+/// Commits to a record's document hash and, in order, its parents' lineage hashes
+pub fn lineage_hash(hash: &str, parent_lineage_hashes: &[[u8; 32]]) -> [u8; 32] {
+    let mut parts: Vec<&[u8]> = vec![hash.as_bytes()];
+    parts.extend(parent_lineage_hashes.iter().map(|h| h.as_ref()));
+    hashv(&parts).to_bytes()
+}
+
+/// Builds a sha256 Merkle root over `leaves`, sorted and deduplicated so the
+/// result is independent of input order. An unpaired node is promoted unchanged.
+pub fn merkle_root(mut leaves: Vec<[u8; 32]>) -> [u8; 32] {
+    leaves.sort();
+    leaves.dedup();
+    if leaves.is_empty() {
+        return [0u8; 32];
+    }
+    while leaves.len() > 1 {
+        leaves = leaves
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hashv(&[left, right]).to_bytes(),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    leaves[0]
+}
+
+/// Folds `leaf` up through its `proof` siblings, in the pairing order of `merkle_root`.
+/// Bit `i` of `directions` puts `proof[i]` on the left.
+pub fn merkle_root_from_proof(leaf: [u8; 32], proof: &[[u8; 32]], directions: u32) -> [u8; 32] {
+    proof.iter().enumerate().fold(leaf, |node, (i, sibling)| {
+        if directions & (1 << i) != 0 {
+            hashv(&[sibling, &node]).to_bytes()
+        } else {
+            hashv(&[&node, sibling]).to_bytes()
+        }
+    })
+}
+
+/// Message a scale signs for a weighing: crate_id bytes, then weight and timestamp (LE)
+pub fn weighing_message(crate_id: &str, weight: u32, timestamp: i64) -> Vec<u8> {
+    let mut message = crate_id.as_bytes().to_vec();
+    message.extend_from_slice(&weight.to_le_bytes());
+    message.extend_from_slice(&timestamp.to_le_bytes());
+    message
+}
+
+/// How far the `signed_at` of a possession proof may be from the cluster clock
+pub const POSSESSION_PROOF_WINDOW_SECS: u64 = 300;
+
+/// Message the crate authority signs to prove possession: crate and receiver pubkeys,
+/// nonce, then `signed_at` (LE)
+pub fn possession_message(
+    crate_record: &Pubkey,
+    receiver: &Pubkey,
+    nonce: &[u8; 32],
+    signed_at: i64,
+) -> Vec<u8> {
+    let mut message = crate_record.to_bytes().to_vec();
+    message.extend_from_slice(receiver.as_ref());
+    message.extend_from_slice(nonce);
+    message.extend_from_slice(&signed_at.to_le_bytes());
+    message
+}
+
+/// Checks that the instruction preceding the current one is an Ed25519 program
+/// verification of a single signature by `signer` over exactly `message`.
+fn verify_ed25519_signature(instructions: &AccountInfo, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    let current = load_current_index_checked(instructions)?;
+    require!(current > 0, ErrorCode::MissingSignatureVerification);
+    let ix = load_instruction_at_checked(current as usize - 1, instructions)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, ErrorCode::MissingSignatureVerification);
+
+    // Layout: [num_signatures, padding, offsets (7 x u16)], with offsets pointing into this ix
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, ErrorCode::InvalidSignatureVerification);
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let [signature_ix, pubkey_offset, pubkey_ix, message_offset, message_size, message_ix] =
+        [4, 6, 8, 10, 12, 14].map(read_u16);
+    require!(
+        signature_ix == u16::MAX && pubkey_ix == u16::MAX && message_ix == u16::MAX,
+        ErrorCode::InvalidSignatureVerification
+    );
+
+    let pubkey_offset = pubkey_offset as usize;
+    let message_offset = message_offset as usize;
+    let signed_pubkey = data.get(pubkey_offset..pubkey_offset + 32);
+    let signed_message = data.get(message_offset..message_offset + message_size as usize);
+    require!(
+        signed_pubkey == Some(signer.as_ref()) && signed_message == Some(message),
+        ErrorCode::InvalidSignatureVerification
+    );
+    Ok(())
+}
+
+// ===================
+// ERRORS
+// ===================
+
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Transfer weight differs from parent beyond tolerance")]
+    WeightMismatchOnTransfer,
+    #[msg("Mix requires at least 2 parents")]
+
+    MixRequiresMultipleParents,
+    #[msg("Split requires at least 2 children")]
+    SplitRequiresMultipleChildren,
+
+    #[msg("Too many parents (max 10)")]
+    TooManyParents,
+    #[msg("Too many children (max 10)")]
+    TooManyChildren,
+
+    #[msg("Child key/weight mismatch")]
+    ChildKeyWeightMismatch,
+    #[msg("Split weights do not sum to parent weight")]
+    SplitWeightMismatch,
+
+    #[msg("Weight overflow")]
+    WeightOverflow,
+    #[msg("Unauthorized update attempt")]
+    UnauthorizedUpdate,
+
+    #[msg("Operation id already processed")]
+    AlreadyProcessed,
+
+    #[msg("Only the config admin can perform this action")]
+    UnauthorizedAdmin,
+    #[msg("Split bps must sum to 10000")]
+    InvalidBpsTotal,
+    #[msg("Crate record is not one of the split children")]
+    ChildNotInSplit,
+
+    #[msg("Crate is frozen")]
+    CrateFrozen,
+    #[msg("Crate is not owned by the target authority")]
+    AuthorityMismatch,
+
+    #[msg("Linking these crates would create a lineage cycle")]
+    LineageCycleDetected,
+    #[msg("Lineage walk exceeded the maximum number of hops")]
+    LineageWalkTooDeep,
+
+    #[msg("Status transition not allowed")]
+    InvalidStatusTransition,
+    #[msg("Status history is full (max 8)")]
+    StatusHistoryFull,
+
+    #[msg("Scale is not registered")]
+    UnregisteredScale,
+    #[msg("Too many registered scales (max 8)")]
+    TooManyRegisteredScales,
+    #[msg("Weighed weight does not match the recorded weight")]
+    WeighingWeightMismatch,
+    #[msg("Missing Ed25519 signature verification instruction")]
+    MissingSignatureVerification,
+    #[msg("Ed25519 verification does not match the expected signer or message")]
+    InvalidSignatureVerification,
+
+    #[msg("Split remainder must be positive")]
+    InvalidSplitRemainder,
+
+    #[msg("Access log is full (max 8)")]
+    AccessLogFull,
+
+    #[msg("Hash already registered to another crate")]
+    DuplicateHash,
+    #[msg("Hash entry account required in strict hash mode")]
+    HashEntryRequired,
+    #[msg("Hash entry account does not match the hash")]
+    InvalidHashEntry,
+
+    #[msg("Timestamp is earlier than a parent's timestamp")]
+    TimestampBeforeParent,
+
+    #[msg("Policy id too long (max 32 bytes)")]
+    PolicyIdTooLong,
+    #[msg("Too many insurance policies (max 3)")]
+    TooManyInsurancePolicies,
+
+    #[msg("Sampler must be the crate authority or a registered lab")]
+    UnauthorizedSampler,
+    #[msg("Sample weight must be positive and less than the crate weight")]
+    InvalidSampleWeight,
+    #[msg("Too many samples (max 5)")]
+    TooManySamples,
+    #[msg("Too many registered labs (max 8)")]
+    TooManyRegisteredLabs,
+
+    #[msg("Account is not an ancestor of the crate")]
+    NotAnAncestor,
+    #[msg("Too many ancestors for a provenance root (max 16)")]
+    TooManyProvenanceAncestors,
+
+    #[msg("Operation is disabled by the program config")]
+    OperationDisabled,
+
+    #[msg("Crate already belongs to a shipment")]
+    CrateAlreadyInShipment,
+    #[msg("Crate is not in this shipment")]
+    CrateNotInShipment,
+    #[msg("Shipment is full (max 10 crates)")]
+    ShipmentFull,
+    #[msg("Accounts do not match the shipment members")]
+    ShipmentMembersMismatch,
+
+    #[msg("Possession nonce was already used")]
+    StaleNonce,
+
+    #[msg("Tolerance cannot exceed 10000 bps")]
+    InvalidTolerance,
+
+    #[msg("Lineage vectors are out of sync")]
+    LineageInvariantBroken,
+
+    #[msg("Certification standard too long (max 32 bytes)")]
+    StandardTooLong,
+    #[msg("Too many certifications (max 3)")]
+    TooManyCertifications,
+    #[msg("Crate needs a valid certification to be transferred")]
+    CertificationRequired,
+
+    #[msg("Creation would exceed the authority's production quota")]
+    ProductionQuotaExceeded,
+
+    #[msg("Encrypted notes too large (max 512 bytes)")]
+    NotesTooLarge,
+    #[msg("Notes key CID too long (max 64 bytes)")]
+    NotesKeyCidTooLong,
+
+    #[msg("Account is not a parent of the child crate")]
+    NotAParentOfChild,
+
+    #[msg("No certification at this index")]
+    CertificationNotFound,
+    #[msg("Only the issuing certifier can revoke a certification")]
+    UnauthorizedCertifier,
+
+    #[msg("Too many accounts to process within the compute budget")]
+    BatchTooLargeForComputeBudget,
+
+    #[msg("Product type too long (max 32 bytes)")]
+    ProductTypeTooLong,
+
+    #[msg("Transfer changed the crate's hash or IPFS CID")]
+    TransferAlteredContent,
+
+    #[msg("Crate has a pending escrow transfer")]
+    EscrowPending,
+    #[msg("Crate has no pending escrow transfer")]
+    NoPendingEscrow,
+    #[msg("Signer is not the escrow's release authority")]
+    UnauthorizedReleaseAuthority,
+    #[msg("Escrow has not been released yet")]
+    EscrowNotReleased,
+
+    #[msg("Declared mix output differs from the input beyond tolerance")]
+    MixConservationExceeded,
+
+    #[msg("Label too long (max 48 bytes)")]
+    LabelTooLong,
+
+    #[msg("Accounts do not match the crate's parents")]
+    ParentSetMismatch,
+
+    #[msg("Delegation has expired")]
+    DelegateExpired,
+
+    #[msg("Crate is already bound to a purchase order")]
+    CrateAlreadyOnPo,
+    #[msg("Crate would exceed the purchase order's expected weight")]
+    PoOverfulfilled,
+
+    #[msg("Crate is not part of a split distribution")]
+    ChildNotInDistribution,
+
+    #[msg("Too many media attachments (max 4)")]
+    TooManyMedia,
+    #[msg("Media CID, MIME type or label too long")]
+    MediaFieldTooLong,
+
+    #[msg("Void grace window has passed, tombstone the crate instead")]
+    VoidWindowExpired,
+    #[msg("Crate already has children")]
+    CrateHasChildren,
+    #[msg("Crate is already tombstoned")]
+    AlreadyTombstoned,
+
+    #[msg("Account is not owned by this program")]
+    NotProgramOwned,
+
+    #[msg("Parent expiries are too far apart to mix")]
+    ExpirySpreadTooLarge,
+
+    #[msg("Signer is not a registered customs authority")]
+    NotCustomsAuthority,
+    #[msg("Too many customs authorities (max 8)")]
+    TooManyCustomsAuthorities,
+    #[msg("Too many customs clearances (max 4)")]
+    TooManyCustomsClearances,
+    #[msg("Port or clearance document CID too long")]
+    CustomsFieldTooLong,
+
+    #[msg("Parent keys must be sorted ascending without duplicates")]
+    ParentsNotSorted,
+
+    #[msg("Ownership shares must sum to 10000 bps")]
+    SharesDontSumTo10000,
+    #[msg("Too many owners (max 8)")]
+    TooManyOwners,
+    #[msg("Sender does not hold enough shares")]
+    InsufficientShares,
+
+    #[msg("Vault controller is not approved")]
+    UnapprovedVaultController,
+    #[msg("Too many vault controllers (max 4)")]
+    TooManyVaultControllers,
+    #[msg("Crate is not held in a vault")]
+    NotInVault,
+
+    #[msg("Child accounts or weight do not follow child_keys order")]
+    ChildOrderMismatch,
+
+    #[msg("Too many operations in the current rate limit window")]
+    RateLimitExceeded,
+    #[msg("max_ops_per_window cannot exceed 32")]
+    InvalidRateLimit,
+
+    #[msg("Return reason CID too long (max 64)")]
+    ReturnReasonCidTooLong,
+
+    #[msg("hash and ipfs_cid must both be set or both be empty")]
+    InconsistentDocumentRefs,
+
+    #[msg("CID must be 1-64 alphanumeric characters")]
+    InvalidCid,
+    #[msg("Too many state checkpoints (max 4)")]
+    TooManyCheckpoints,
+
+    #[msg("Batch outputs plus declared loss do not equal inputs")]
+    BatchConservationFailed,
+
+    #[msg("Crate is still within its minimum holding period")]
+    HoldPeriodNotMet,
+
+    #[msg("Weight corrections need a reason CID (max 64)")]
+    CorrectionReasonRequired,
+    #[msg("Too many weight corrections (max 3)")]
+    TooManyWeightCorrections,
+
+    #[msg("A field required by this deployment is empty")]
+    RequiredFieldMissing,
+
+    #[msg("Account passed more than once")]
+    DuplicateAccount,
+
+    #[msg("Lot code exceeds maximum length (32)")]
+    LotCodeTooLong,
+    #[msg("Mixed parents must share a lot code")]
+    LotCodeMismatch,
+
+    #[msg("Excursion min_temp exceeds max_temp")]
+    InvalidExcursion,
+    #[msg("Too many excursions (max 4)")]
+    TooManyExcursions,
+
+    #[msg("Transfer SLA has not expired yet")]
+    SlaNotExpired,
+    #[msg("Transfer SLA expired before acceptance")]
+    SlaExpired,
+
+    #[msg("Split children exceed the parent weight")]
+    SplitOverAllocated,
+
+    #[msg("DPP id must be 1-64 printable ASCII characters")]
+    InvalidDppId,
+    #[msg("Mix would carry too many product passports (max 4)")]
+    TooManyDppIds,
+
+    #[msg("Supplier name or contact CID too long")]
+    SupplierFieldTooLong,
+
+    #[msg("display_decimals cannot exceed 6")]
+    DisplayDecimalsTooLarge,
+
+    #[msg("Crate or one of its ancestors is recalled")]
+    CrateIsRecalled,
+
+    #[msg("High-value crate needs a co-signer from high_value_approvers")]
+    HighValueApprovalRequired,
+    #[msg("Too many high-value approvers (max 4)")]
+    TooManyHighValueApprovers,
+
+    #[msg("Repackaging must keep the parent weight")]
+    RepackageWeightChanged,
+
+    #[msg("Mint must be an initialized SPL mint controlled by the crate authority")]
+    InvalidMint,
+    #[msg("Crate is already tokenized")]
+    AlreadyTokenized,
+    #[msg("Tokenized crates transfer via their token")]
+    CrateTokenized,
+
+    #[msg("CID is not from an allowed document source")]
+    DisallowedCidSource,
+    #[msg("Up to 4 non-empty CID prefixes of at most 32 bytes")]
+    InvalidCidPrefixes,
+
+    #[msg("parent_grades must have one grade per parent")]
+    GradeCountMismatch,
+
+    #[msg("Lineage edge is disputed")]
+    EdgeDisputed,
+    #[msg("Lineage edge is not disputed")]
+    EdgeNotDisputed,
+    #[msg("Too many edge disputes (max 4)")]
+    TooManyEdgeDisputes,
+
+    #[msg("Crate already has a scheduled transfer")]
+    TransferAlreadyScheduled,
+    #[msg("Crate has no scheduled transfer")]
+    NoScheduledTransfer,
+    #[msg("Scheduled transfer is not effective yet")]
+    NotYetEffective,
+
+    #[msg("Location must be \"lat,lon\" in decimal degrees")]
+    InvalidCoordinates,
+
+    #[msg("Split children must have distinct authorities")]
+    DuplicateChildAuthority,
+
+    #[msg("Crate is quarantined pending inspection")]
+    CrateQuarantined,
+    #[msg("Crate is not quarantined")]
+    CrateNotQuarantined,
+
+    #[msg("Crate has reached the maximum number of transfers")]
+    MaxTransfersReached,
+
+    #[msg("Merkle proof does not match the provenance root")]
+    InvalidMerkleProof,
+
+    #[msg("Pool holds less than the requested weight")]
+    InsufficientPoolBalance,
+
+    #[msg("Not every required party has signed off the checkpoint")]
+    CheckpointIncomplete,
+    #[msg("Too many checkpoint parties (max 4)")]
+    TooManyCheckpointParties,
+    #[msg("Party has already signed off this checkpoint")]
+    AlreadySignedOff,
+
+    #[msg("Operation statuses must be unique per operation and not Registered (max 8)")]
+    InvalidOperationStatuses,
+
+    #[msg("Crate id has no reservation held by the creator")]
+    CrateIdNotReserved,
+    #[msg("Crate id reservation has expired")]
+    ReservationExpired,
+    #[msg("Crate id is already reserved")]
+    CrateIdAlreadyReserved,
+
+    #[msg("Transfer requires a passing inspection")]
+    InspectionRequired,
+    #[msg("Inspection report CID too long")]
+    InspectionReportTooLong,
+    #[msg("Too many inspections (max 4)")]
+    TooManyInspections,
+    #[msg("No inspection at that index")]
+    InspectionNotFound,
+    #[msg("Too many recipients for one split_to_many (max 4)")]
+    SplitFanoutTooLarge,
+    #[msg("Each recipient needs its own new signer account, in order")]
+    SplitAccountMismatch,
+    #[msg("Crate was already consumed into child crates")]
+    CrateConsumed,
+    #[msg("Parent accounts must match parent_keys, in order")]
+    ParentAccountMismatch,
+    #[msg("Crate is past its expiry")]
+    CrateExpired,
+    #[msg("Possession proof is not newer than the last one or outside the signing window")]
+    StalePossessionProof,
+}